@@ -0,0 +1,943 @@
+// What items do when they are used, equipped or crafted
+
+use super::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Item {
+	Heal,
+	Lightning,
+	Confuse,
+	Fireball,
+	FireFlask,
+	Food,
+	SummonAlly,
+	Spellbook(Spell),
+	GreaterHeal,
+	Digging(i32), // charges left
+	DetectMonsters,
+	SeeInvisible,
+	Net,
+	BagOfHolding,
+	Sword,
+	Shield,
+	Helmet,
+	Mapping,
+	Dagger,
+	RemoveCurse,
+}
+
+// Where a piece of equipment is worn; only one thing fits in each
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Slot {
+	LeftHand,
+	RightHand,
+	Head,
+}
+
+impl Slot {
+	pub(crate) fn name(self) -> &'static str {
+		match self {
+			Slot::LeftHand => "left hand",
+			Slot::RightHand => "right hand",
+			Slot::Head => "head",
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Equipment {
+	pub(crate) slot: Slot,
+	pub(crate) equipped: bool,
+	pub(crate) power_bonus: i32,
+	pub(crate) defense_bonus: i32,
+	pub(crate) max_hp_bonus: i32,
+	// won't come off until the curse is lifted; nobody can tell before putting it on
+	#[serde(default)]
+	pub(crate) cursed: bool,
+}
+
+// The equipment component for items that can be worn
+pub(crate) fn equipment_for(item: Item) -> Option<Equipment> {
+	let (slot, power_bonus, defense_bonus, max_hp_bonus) = match item {
+		Item::Sword => (Slot::RightHand, SWORD_POWER_BONUS, 0, 0),
+		Item::Shield => (Slot::LeftHand, 0, SHIELD_DEFENSE_BONUS, 0),
+		Item::Helmet => (Slot::Head, 0, 0, HELMET_MAX_HP_BONUS),
+		_ => return None,
+	};
+	Some(Equipment { slot, equipped: false, power_bonus, defense_bonus, max_hp_bonus, cursed: false })
+}
+
+// A cursed piece takes away what it should have given
+pub(crate) fn curse(equipment: &mut Equipment) {
+	equipment.cursed = true;
+	for bonus in [&mut equipment.power_bonus, &mut equipment.defense_bonus, &mut equipment.max_hp_bonus] {
+		if *bonus > 0 {
+			*bonus = -CURSE_PENALTY;
+		}
+	}
+}
+
+// Spells the player can learn from a spellbook and cast with mana
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Spell {
+	Heal,
+	Lightning,
+	Confuse,
+	Fireball,
+}
+
+impl Spell {
+	pub(crate) fn name(self) -> &'static str {
+		match self {
+			Spell::Heal => "heal",
+			Spell::Lightning => "lightning bolt",
+			Spell::Confuse => "confusion",
+			Spell::Fireball => "fireball",
+		}
+	}
+
+	pub(crate) fn cost(self) -> i32 {
+		match self {
+			Spell::Heal => 5,
+			Spell::Lightning => 8,
+			Spell::Confuse => 6,
+			Spell::Fireball => 12,
+		}
+	}
+
+	// the same effects the scrolls and potions have
+	pub(crate) fn effect(self) -> UseFn {
+		match self {
+			Spell::Heal => cast_heal,
+			Spell::Lightning => cast_lightning,
+			Spell::Confuse => cast_confuse,
+			Spell::Fireball => cast_fireball,
+		}
+	}
+}
+
+// A crafting recipe: every input item is used up to make the output
+pub(crate) struct Recipe {
+	pub(crate) inputs: &'static [Item],
+	pub(crate) output: Item,
+	pub(crate) name: &'static str,
+	pub(crate) char: char,
+	pub(crate) color: Color,
+}
+
+pub(crate) enum UseResult {
+	UsedUp,
+	Kept, // used, but the item stays in the inventory
+	Thrown, // used up as an attack, which costs a turn like any other
+	Cancelled,
+}
+
+impl UseResult {
+	// whether the monsters get to move afterwards; using most items is free
+	pub fn took_turn(&self) -> bool {
+		matches!(self, UseResult::Thrown)
+	}
+}
+
+// Every item and spell effect has this one signature. Some of them spawn objects, so it takes the
+// Vec, and the effects that never push allow clippy::ptr_arg to keep to it
+pub(crate) type UseFn = fn(usize, &mut Vec<Object>, &mut Game, &mut Tcod) -> UseResult;
+
+pub(crate) fn pick_item_up(object_id: usize, objects: &mut Vec<Object>, game: &mut Game) {
+	if game.inventory.len() >= game.inventory_capacity {
+		game.log.add(format!("Your inventory is full, cannot pick up {}.", objects[object_id].name), colors::RED);
+	} else {
+		let item = objects.swap_remove(object_id);
+		game.log.add(format!("You picked up a {}!", item.name), colors::GREEN);
+		if item.item.is_some_and(is_rare) {
+			add_journal_entry(game, format!("Found a {}.", item.name));
+		}
+		if let Some(hint) = upgrade_hint(&item, &game.inventory) {
+			game.log.add(hint, colors::LIGHT_GREEN);
+		}
+		game.inventory.push(item);
+		wear_if_cursed(game.inventory.len() - 1, &mut objects[PLAYER], game);
+	}
+}
+
+// Cursed gear gets onto the player as soon as they touch it, pushing off whatever was in its slot.
+// Only another cursed piece, which won't come off, keeps it in the pack
+fn wear_if_cursed(inventory_id: usize, player: &mut Object, game: &mut Game) {
+	let equipment = match game.inventory[inventory_id].equipment {
+		Some(equipment) if equipment.cursed && !equipment.equipped => equipment,
+		_ => return,
+	};
+	let worn = game.inventory.iter().position(|item| {
+		item.equipment.is_some_and(|e| e.equipped && e.slot == equipment.slot)
+	});
+	if let Some(worn_id) = worn {
+		if !unequip(worn_id, player, game) {
+			return;
+		}
+	}
+	equip(inventory_id, game);
+	game.log.add(format!("The {} clamps onto you. It's cursed!", game.inventory[inventory_id].name), colors::RED);
+	cap_hp(player, game);
+}
+
+// A nudge to put on equipment that beats what's worn in its slot: no bonus lower, at least one higher
+pub(crate) fn upgrade_hint(item: &Object, inventory: &[Object]) -> Option<String> {
+	let new = item.equipment?;
+	let worn = inventory.iter().find(|other| other.equipment.is_some_and(|e| e.equipped && e.slot == new.slot));
+	let bonuses = |e: Equipment| [e.power_bonus, e.defense_bonus, e.max_hp_bonus];
+	let old = worn.and_then(|worn| worn.equipment).map_or([0; 3], bonuses);
+	let better = bonuses(new).iter().zip(&old).all(|(new, old)| new >= old)
+		&& bonuses(new).iter().zip(&old).any(|(new, old)| new > old);
+	if !better {
+		return None;
+	}
+	Some(match worn {
+		Some(worn) => format!("The {} looks stronger than your {}. Press i to equip it.", item.name, worn.name),
+		None => format!("Nothing is on your {}. Press i to equip the {}.", new.slot.name(), item.name),
+	})
+}
+
+// Finds that go in the journal
+pub(crate) fn is_rare(item: Item) -> bool {
+	matches!(item, Item::Spellbook(_) | Item::BagOfHolding)
+}
+
+// Moves what the corpse carries into the inventory, and tells whether anything was taken
+pub(crate) fn loot_corpse(corpse_id: usize, objects: &mut [Object], game: &mut Game) -> bool {
+	if objects[corpse_id].loot.is_empty() {
+		game.log.add(format!("There is nothing to loot on the {}.", objects[corpse_id].name),
+			colors::WHITE);
+		return false;
+	}
+
+	game.log.add(format!("You loot the {}.", objects[corpse_id].name), colors::GREEN);
+	let carried = objects[corpse_id].loot.len();
+	while let Some(item) = objects[corpse_id].loot.pop() {
+		if game.inventory.len() >= game.inventory_capacity {
+			game.log.add(format!("Your inventory is full, cannot take the {}.", item.name), colors::RED);
+			objects[corpse_id].loot.push(item);
+			break;
+		}
+		game.log.add(format!("You take a {}!", item.name), colors::GREEN);
+		if item.item.is_some_and(is_rare) {
+			add_journal_entry(game, format!("Found a {}.", item.name));
+		}
+		game.inventory.push(item);
+		wear_if_cursed(game.inventory.len() - 1, &mut objects[PLAYER], game);
+	}
+	objects[corpse_id].loot.len() < carried
+}
+
+////////////////////
+/////////////
+/////////
+/////    SPELLS
+/////////
+/////////////
+///////////////////
+
+
+#[allow(clippy::ptr_arg)]
+pub(crate) fn cast_heal(_inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, _tcod: &mut Tcod)
+             -> UseResult
+{
+    // heal the player
+    if let Some(fighter) = objects[PLAYER].fighter {
+        if fighter.hp >= objects[PLAYER].max_hp(game) {
+            game.log.add("You are already at full health.", colors::RED);
+            return UseResult::Cancelled;
+        }
+        game.log.add("Your wounds start to feel better!", colors::LIGHT_VIOLET);
+        objects[PLAYER].heal(HEAL_AMOUNT, game);
+        return UseResult::UsedUp;
+    }
+    UseResult::Cancelled
+}
+
+#[allow(clippy::ptr_arg)]
+pub(crate) fn cast_greater_heal(_inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, _tcod: &mut Tcod)
+                     -> UseResult
+{
+    if let Some(fighter) = objects[PLAYER].fighter {
+        if fighter.hp >= objects[PLAYER].max_hp(game) && objects[PLAYER].drained == 0 {
+            game.log.add("You are already at full health.", colors::RED);
+            return UseResult::Cancelled;
+        }
+        // also brings back life that was drained
+        if objects[PLAYER].drained > 0 {
+            game.log.add("You feel your life force return!", colors::LIGHT_VIOLET);
+            objects[PLAYER].restore_life();
+        }
+        game.log.add("Your wounds close up!", colors::LIGHT_VIOLET);
+        objects[PLAYER].heal(GREATER_HEAL_AMOUNT, game);
+        return UseResult::UsedUp;
+    }
+    UseResult::Cancelled
+}
+
+#[allow(clippy::ptr_arg)]
+pub(crate) fn cast_lightning(_inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod)
+                  -> UseResult
+{
+    // find closest enemy (inside a maximum range and damage it)
+    let monster_id = closest_monster(LIGHTNING_RANGE, objects, game, tcod);
+    if let Some(monster_id) = monster_id {
+        // zap it!
+        game.log.add(format!("A lightning bolt strikes the {} with a loud thunder! \
+                              The damage is {} hit points.",
+                             objects[monster_id].name, LIGHTNING_DAMAGE),
+                     colors::LIGHT_CYAN);
+        objects[monster_id].take_damage(LIGHTNING_DAMAGE, &mut game.log, &mut game.stats);
+        UseResult::UsedUp
+    } else {  // no enemy found within maximum range
+        game.log.add("No enemy is close enough to strike.", colors::RED);
+        UseResult::Cancelled
+    }
+}
+
+#[allow(clippy::ptr_arg)]
+pub(crate) fn cast_confuse(_inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod)
+                -> UseResult
+{
+    // ask the player for a target to confuse
+    game.log.add("Left-click an enemy to confuse it, or right-click to cancel.",
+            colors::LIGHT_CYAN);
+    let monster_id = target_monster(tcod, objects, game, Some(CONFUSE_RANGE as f32));
+    if let Some(monster_id) = monster_id {
+        if objects[monster_id].is_immune(Effect::Confusion) {
+            game.log.add(format!("The {} is unaffected.", objects[monster_id].name), colors::WHITE);
+            return UseResult::UsedUp;
+        }
+        let old_ai = objects[monster_id].ai.take().unwrap_or(Ai::Basic);
+        // replace the monster's AI with a "confused" one; after
+        // some turns it will restore the old AI
+        objects[monster_id].ai = Some(Ai::Confused {
+            previous_ai: Box::new(old_ai),
+            num_turns: CONFUSE_NUM_TURNS,
+        });
+        game.log.add(format!("The eyes of {} look vacant, as he starts to stumble around!",
+                             objects[monster_id].name),
+                     colors::LIGHT_GREEN);
+        UseResult::UsedUp
+    } else {  // no enemy fonud within maximum range
+        game.log.add("No enemy is close enough to strike.", colors::RED);
+        UseResult::Cancelled
+    }
+}
+
+pub(crate) fn cast_fireball(_inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod)
+                 -> UseResult
+{
+    // ask the player for a target tile to throw a fireball at
+    game.log.add("Left-click a target tile for the fireball, or right-click to cancel.",
+            colors::LIGHT_CYAN);
+    let (x, y) = match target_tile(tcod, objects, game, None, Some(FIREBALL_RADIUS as f32)) {
+        Some(tile_pos) => tile_pos,
+        None => return UseResult::Cancelled,
+    };
+    game.log.add(format!("The fireball explodes, burning everything within {} tiles!", FIREBALL_RADIUS),
+            colors::ORANGE);
+
+    for obj in objects {
+        if obj.distance(x, y) <= FIREBALL_RADIUS as f32 && obj.fighter.is_some() {
+            if obj.is_immune(Effect::Fire) {
+                game.log.add(format!("The {} is unaffected.", obj.name), colors::WHITE);
+                continue;
+            }
+            game.log.add(format!("The {} gets burned for {} hit points.", obj.name, FIREBALL_DAMAGE),
+                    colors::ORANGE);
+            obj.take_damage(FIREBALL_DAMAGE, &mut game.log, &mut game.stats);
+
+        }
+    }
+
+    UseResult::UsedUp
+}
+
+pub(crate) fn cast_throw(inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod)
+              -> UseResult
+{
+    // ask the player for a target to throw at
+    game.log.add("Left-click an enemy to throw at, or right-click to cancel.",
+            colors::LIGHT_CYAN);
+    let monster_id = match target_monster(tcod, objects, game, Some(THROW_RANGE as f32)) {
+        Some(monster_id) => monster_id,
+        None => return UseResult::Cancelled,
+    };
+    let target = objects[monster_id].pos();
+    if !clear_shot(objects[PLAYER].pos(), target, &game.map, objects) {
+        game.log.add("Something is in the way.", colors::RED);
+        return UseResult::Cancelled;
+    }
+
+    game.log.add(format!("You throw the {} at the {}.", game.inventory[inventory_id].name, objects[monster_id].name),
+            colors::WHITE);
+    let (player, monster) = mut_two(PLAYER, monster_id, objects);
+    player.attack(monster, game);
+
+    // it lands where the monster stood, to be picked up again
+    let mut thrown = game.inventory[inventory_id].clone();
+    thrown.set_pos(target.0, target.1);
+    objects.push(thrown);
+    UseResult::Thrown
+}
+
+#[allow(clippy::ptr_arg)]
+pub(crate) fn cast_dig(inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod)
+            -> UseResult
+{
+    let charges = match game.inventory[inventory_id].item {
+        Some(Item::Digging(charges)) => charges,
+        _ => return UseResult::Cancelled,
+    };
+
+    // ask the player which way to dig
+    game.log.add("Left-click a tile to dig towards, or right-click to cancel.",
+            colors::LIGHT_CYAN);
+    let target = match target_tile(tcod, objects, game, Some(DIG_RANGE as f32), None) {
+        Some(tile_pos) => tile_pos,
+        None => return UseResult::Cancelled,
+    };
+
+    let (player_x, player_y) = objects[PLAYER].pos();
+    let (dx, dy) = (target.0 - player_x, target.1 - player_y);
+    if (dx, dy) == (0, 0) {
+        return UseResult::Cancelled;
+    }
+    // only a wall's face can be clicked, so the target just gives the direction:
+    // the line carries on past it until it is DIG_RANGE long
+    let scale = DIG_RANGE / cmp::max(dx.abs(), dy.abs()) + 1;
+    let end = (player_x + dx * scale, player_y + dy * scale);
+
+    // carve every wall along the line, but never the outer wall of the map
+    let line = tcod::line::Line::new((player_x, player_y), end)
+        .take_while(|&(x, y)| objects[PLAYER].distance(x, y) <= DIG_RANGE as f32);
+    for (x, y) in line {
+        if x <= 0 || y <= 0 || x >= MAP_WIDTH - 1 || y >= MAP_HEIGHT - 1 {
+            break;
+        }
+        // a fresh bit of tunnel: whatever the rock was (a door, a net) is gone
+        *game.map.get_mut(x, y) = Tile::floor(Some(TileType::Corridor));
+        tcod.fov.set(x, y, true, true);
+    }
+    tcod.fov.compute_fov(player_x, player_y, TORCH_RADIUS, FOV_LIGHT_WALLS, FOV_ALGO);
+    game.log.add("The rock crumbles away before you!", colors::SEPIA);
+
+    if charges > 1 {
+        game.inventory[inventory_id].item = Some(Item::Digging(charges - 1));
+        UseResult::Kept
+    } else {
+        game.log.add("The wand crumbles to dust.", colors::LIGHT_GREY);
+        UseResult::UsedUp
+    }
+}
+
+pub(crate) fn cast_detect_monsters(_inventory_id: usize, _objects: &mut Vec<Object>, game: &mut Game, _tcod: &mut Tcod)
+                        -> UseResult
+{
+    game.log.add("You sense the presence of every monster on the level!", colors::LIGHT_CYAN);
+    game.detect_turns = DETECT_NUM_TURNS;
+    UseResult::UsedUp
+}
+
+pub(crate) fn cast_map_reveal(_inventory_id: usize, _objects: &mut Vec<Object>, game: &mut Game, _tcod: &mut Tcod)
+                   -> UseResult
+{
+    // only the terrain; monsters and items still have to be seen
+    for tile in game.map.tiles.iter_mut() {
+        tile.explored = true;
+    }
+    if TILE_MEMORY {
+        game.log.add("The dungeon's layout is revealed to you!", colors::LIGHT_BLUE);
+    } else {
+        // in true darkness nothing out of view is drawn, so the map is lost as soon as it is seen
+        game.log.add("The dungeon's layout flashes before your eyes, then fades.", colors::LIGHT_BLUE);
+    }
+    UseResult::UsedUp
+}
+
+pub(crate) fn cast_remove_curse(_inventory_id: usize, _objects: &mut Vec<Object>, game: &mut Game, _tcod: &mut Tcod)
+                     -> UseResult
+{
+    if lift_curses(&mut game.inventory) {
+        game.log.add("A weight lifts from you. Your gear will come off again.", colors::LIGHT_BLUE);
+    } else {
+        game.log.add("You feel as if someone is watching over you.", colors::LIGHT_BLUE);
+    }
+    UseResult::UsedUp
+}
+
+pub(crate) fn cast_see_invisible(_inventory_id: usize, _objects: &mut Vec<Object>, game: &mut Game, _tcod: &mut Tcod)
+                      -> UseResult
+{
+    game.log.add("Your eyes tingle, and the air seems to shimmer.", colors::LIGHT_CYAN);
+    game.see_invisible_turns = SEE_INVISIBLE_NUM_TURNS;
+    UseResult::UsedUp
+}
+
+#[allow(clippy::ptr_arg)]
+pub(crate) fn cast_fire_flask(_inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod)
+                   -> UseResult
+{
+    // ask the player where to throw the flask
+    game.log.add("Left-click a target tile to throw the flask, or right-click to cancel.",
+            colors::LIGHT_CYAN);
+    let (x, y) = match target_tile(tcod, objects, game, Some(FIRE_FLASK_RANGE as f32), None) {
+        Some(tile_pos) => tile_pos,
+        None => return UseResult::Cancelled,
+    };
+    game.log.add("The flask shatters and the floor bursts into flames!",
+            colors::ORANGE);
+
+    // set the floor around the landing spot on fire; the fire never grows
+    // beyond this initial splash
+    for fx in (x - FIRE_FLASK_RADIUS)..(x + FIRE_FLASK_RADIUS + 1) {
+        for fy in (y - FIRE_FLASK_RADIUS)..(y + FIRE_FLASK_RADIUS + 1) {
+            if !game.map.in_bounds(fx, fy) {
+                continue;
+            }
+            let tile = game.map.get_mut(fx, fy);
+            if !tile.blocked {
+                tile.burning = FIRE_NUM_TURNS;
+            }
+        }
+    }
+
+    UseResult::UsedUp
+}
+
+
+#[allow(clippy::ptr_arg)]
+pub(crate) fn cast_net(_inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod)
+            -> UseResult
+{
+    game.log.add("Left-click a tile to spread the net on, or right-click to cancel.",
+            colors::LIGHT_CYAN);
+    let (x, y) = match target_tile(tcod, objects, game, Some(NET_RANGE as f32), None) {
+        Some(tile_pos) => tile_pos,
+        None => return UseResult::Cancelled,
+    };
+    if game.map.get(x, y).blocked || objects_at(x, y, objects).iter().any(|&id| objects[id].blocks) {
+        game.log.add("There's no room to lay a net there.", colors::RED);
+        return UseResult::Cancelled;
+    }
+    game.map.get_mut(x, y).net = true;
+    game.log.add("You spread the net out on the floor.", colors::LIGHT_CYAN);
+    UseResult::UsedUp
+}
+
+// Put the item on, or take it off if it's already worn
+#[allow(clippy::ptr_arg)]
+pub(crate) fn cast_equip(inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, _tcod: &mut Tcod)
+              -> UseResult
+{
+    let equipment = match game.inventory[inventory_id].equipment {
+        Some(equipment) => equipment,
+        None => return UseResult::Cancelled,
+    };
+    if equipment.equipped {
+        unequip(inventory_id, &mut objects[PLAYER], game);
+    } else {
+        // whatever was in that slot comes off first
+        let worn = game.inventory.iter().position(|item| {
+            item.equipment.is_some_and(|e| e.equipped && e.slot == equipment.slot)
+        });
+        if let Some(worn_id) = worn {
+            if !unequip(worn_id, &mut objects[PLAYER], game) {
+                return UseResult::Cancelled;
+            }
+        }
+        equip(inventory_id, game);
+        cap_hp(&mut objects[PLAYER], game);
+    }
+    UseResult::Kept
+}
+
+pub(crate) fn cast_bag_of_holding(_inventory_id: usize, _objects: &mut Vec<Object>, game: &mut Game, _tcod: &mut Tcod)
+                       -> UseResult
+{
+    game.inventory_capacity += BAG_OF_HOLDING_SLOTS;
+    game.log.add("You can now carry more items.", colors::LIGHT_VIOLET);
+    UseResult::UsedUp
+}
+
+pub(crate) fn cast_eat(_inventory_id: usize, _objects: &mut Vec<Object>, game: &mut Game, _tcod: &mut Tcod)
+            -> UseResult
+{
+    if game.satiation >= MAX_SATIATION {
+        game.log.add("You are too full to eat.", colors::RED);
+        return UseResult::Cancelled;
+    }
+    game.log.add("That really hit the spot!", colors::LIGHT_VIOLET);
+    game.satiation = cmp::min(game.satiation + FOOD_AMOUNT, MAX_SATIATION);
+    UseResult::UsedUp
+}
+
+pub(crate) fn cast_summon_ally(_inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, _tcod: &mut Tcod)
+                    -> UseResult
+{
+    // the ally appears on the first free tile next to the player
+    let (player_x, player_y) = objects[PLAYER].pos();
+    let spot = (-1..2)
+        .flat_map(|dx| (-1..2).map(move |dy| (player_x + dx, player_y + dy)))
+        .find(|&(x, y)| !is_blocked(x, y, &game.map, objects));
+    let (x, y) = match spot {
+        Some(spot) => spot,
+        None => {
+            game.log.add("There is no room for anything to appear.", colors::RED);
+            return UseResult::Cancelled;
+        }
+    };
+
+    let mut wolf = Object::new(x, y, 'w', "spectral wolf", colors::LIGHT_BLUE, true);
+    wolf.fighter = Some(Fighter{max_hp: 12, hp: 12, defense: 0, power: 4, on_death: DeathCallBack::Monster,
+                range: 0.0});
+    wolf.ai = Some(Ai::Ally{num_turns: SUMMON_NUM_TURNS, command: AllyCommand::Attack});
+    wolf.attack_verb = Some("bites".into());
+    wolf.alive = true;
+    objects.push(wolf);
+
+    game.log.add("A spectral wolf appears at your side!", colors::LIGHT_BLUE);
+    UseResult::UsedUp
+}
+
+pub(crate) fn cast_learn_spell(inventory_id: usize, _objects: &mut Vec<Object>, game: &mut Game, _tcod: &mut Tcod)
+                    -> UseResult
+{
+    let spell = match game.inventory[inventory_id].item {
+        Some(Item::Spellbook(spell)) => spell,
+        _ => return UseResult::Cancelled,
+    };
+    if game.spells.contains(&spell) {
+        game.log.add(format!("You already know the {} spell.", spell.name()), colors::RED);
+        return UseResult::Cancelled;
+    }
+    game.log.add(format!("You study the book and learn the {} spell!", spell.name()),
+                 colors::LIGHT_BLUE);
+    game.spells.push(spell);
+    add_journal_entry(game, format!("Learned the {} spell.", spell.name()));
+    UseResult::UsedUp
+}
+
+pub(crate) fn cast_spell(spell_id: usize, objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod) {
+    let spell = game.spells[spell_id];
+    if game.mana < spell.cost() {
+        game.log.add(format!("You don't have enough mana to cast {}.", spell.name()), colors::RED);
+        return;
+    }
+    // spells don't come from the inventory, so there is no item to pass along
+    match spell.effect()(0, objects, game, tcod) {
+        UseResult::UsedUp | UseResult::Kept | UseResult::Thrown => game.mana -= spell.cost(),
+        UseResult::Cancelled => game.log.add("Cancelled", colors::WHITE),
+    }
+}
+
+
+pub(crate) fn use_item(inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game, tcod: &mut Tcod)
+                -> UseResult
+{
+    use Item::*;
+    // just call the "use_function" if it is defined
+    if let Some(item) = game.inventory[inventory_id].item {
+        let on_use: UseFn = match item {
+            Heal => cast_heal,
+            Lightning => cast_lightning,
+            Confuse => cast_confuse,
+            Fireball => cast_fireball,
+            FireFlask => cast_fire_flask,
+            Food => cast_eat,
+            SummonAlly => cast_summon_ally,
+            Spellbook(_) => cast_learn_spell,
+            GreaterHeal => cast_greater_heal,
+            Digging(_) => cast_dig,
+            DetectMonsters => cast_detect_monsters,
+            SeeInvisible => cast_see_invisible,
+            Net => cast_net,
+            BagOfHolding => cast_bag_of_holding,
+            Sword | Shield | Helmet => cast_equip,
+            Mapping => cast_map_reveal,
+            Dagger => cast_throw,
+            RemoveCurse => cast_remove_curse,
+        };
+        let result = on_use(inventory_id, objects, game, tcod);
+        match result {
+            UseResult::UsedUp | UseResult::Thrown => {
+                // destroy after use, unless it was cancelled for some reason
+                game.inventory.remove(inventory_id);
+            }
+            UseResult::Kept => {}
+            UseResult::Cancelled => {
+                game.log.add("Cancelled", colors::WHITE);
+            }
+        }
+        result
+    } else {
+        game.log.add(format!("The {} cannot be used.", game.inventory[inventory_id].name),
+                colors::WHITE);
+        UseResult::Cancelled
+    }
+}
+
+// Equipment only counts once it's marked as worn: power(), defense() and max_hp() add it up from there
+pub(crate) fn equip(inventory_id: usize, game: &mut Game) {
+	let item = &mut game.inventory[inventory_id];
+	if let Some(ref mut equipment) = item.equipment {
+		equipment.equipped = true;
+		game.log.add(format!("Equipped {} on {}.", item.name, equipment.slot.name()), colors::LIGHT_GREEN);
+	}
+}
+
+// Tells whether the item came off, which a cursed one never does
+pub(crate) fn unequip(inventory_id: usize, player: &mut Object, game: &mut Game) -> bool {
+	let item = &mut game.inventory[inventory_id];
+	if let Some(ref mut equipment) = item.equipment {
+		if equipment.cursed {
+			game.log.add(format!("The {} is stuck to you!", item.name), colors::RED);
+			return false;
+		}
+		equipment.equipped = false;
+		let text = format!("Unequipped {} from {}.", item.name, equipment.slot.name());
+		game.log.add(text, colors::LIGHT_YELLOW);
+		// taking off a helmet can leave the player above their new max HP
+		cap_hp(player, game);
+	}
+	true
+}
+
+// Frees everything cursed that is worn, the only place a curse shows, and tells whether there was any
+pub(crate) fn lift_curses(inventory: &mut [Object]) -> bool {
+	let mut lifted = false;
+	for equipment in inventory.iter_mut().filter_map(|item| item.equipment.as_mut()) {
+		if equipment.equipped && equipment.cursed {
+			equipment.cursed = false;
+			lifted = true;
+		}
+	}
+	lifted
+}
+
+pub(crate) fn cap_hp(player: &mut Object, game: &Game) {
+	let max_hp = player.max_hp(game);
+	if let Some(ref mut fighter) = player.fighter {
+		fighter.hp = cmp::min(fighter.hp, max_hp);
+	}
+}
+
+// Take off everything at once; the bonuses go with it, and so does HP above the new maximum
+pub(crate) fn unequip_all(player: &mut Object, game: &mut Game) {
+	let worn: Vec<_> = (0..game.inventory.len())
+		.filter(|&id| game.inventory[id].equipment.is_some_and(|e| e.equipped))
+		.collect();
+	if worn.is_empty() {
+		game.log.add("You aren't wearing anything.", colors::WHITE);
+	}
+	for id in worn {
+		unequip(id, player, game);
+	}
+}
+
+// Put away what's worn, remembering it as this loadout, and wear the other loadout instead. Gear is
+// remembered by name, so anything dropped or used up since is just missing
+pub(crate) fn swap_loadout(player: &mut Object, game: &mut Game) {
+	// cursed gear stays on through any swap, so it's in neither loadout
+	let worn: Vec<_> = (0..game.inventory.len())
+		.filter(|&id| game.inventory[id].equipment.is_some_and(|e| e.equipped && !e.cursed))
+		.collect();
+	game.loadouts[game.loadout] = worn.iter().map(|&id| game.inventory[id].name.clone()).collect();
+	game.loadout = 1 - game.loadout;
+	let which = if game.loadout == 1 { "backup" } else { "main" };
+
+	// swapped quietly, with one message for the lot
+	for id in worn {
+		if let Some(ref mut equipment) = game.inventory[id].equipment {
+			equipment.equipped = false;
+		}
+	}
+	let wanted = game.loadouts[game.loadout].clone();
+	if wanted.is_empty() {
+		game.log.add(format!("Your {} loadout is empty, so you put your gear away.", which), colors::LIGHT_YELLOW);
+	} else {
+		game.log.add(format!("Switched to your {} weapons.", which), colors::LIGHT_GREEN);
+	}
+	for name in wanted {
+		let found = game.inventory.iter()
+			.position(|item| item.name == name && item.equipment.is_some_and(|e| !e.equipped));
+		let id = match found {
+			Some(id) => id,
+			None => {
+				game.log.add(format!("Your {} is no longer with you.", name), colors::LIGHT_YELLOW);
+				continue;
+			}
+		};
+		let slot = game.inventory[id].equipment.unwrap().slot;
+		let stuck = game.inventory.iter().find(|item| item.equipment.is_some_and(|e| e.equipped && e.slot == slot));
+		match stuck {
+			Some(stuck) => game.log.add(format!("The {} is stuck to you!", stuck.name), colors::RED),
+			None => game.inventory[id].equipment.as_mut().unwrap().equipped = true,
+		}
+	}
+
+	// only now, so swapping a helmet for the same helmet doesn't cost any HP
+	cap_hp(player, game);
+}
+
+pub(crate) fn drop_item(inventory_id: usize, objects: &mut Vec<Object>, game: &mut Game) {
+	if game.inventory[inventory_id].equipment.is_some_and(|e| e.equipped)
+			&& !unequip(inventory_id, &mut objects[PLAYER], game) {
+		return;
+	}
+	let mut item = game.inventory.remove(inventory_id);
+	item.set_pos(objects[PLAYER].x, objects[PLAYER].y);
+	game.log.add(format!("You dropped a {}.", item.name), colors::YELLOW);
+	objects.push(item);
+}
+
+// Find a distinct inventory slot for every input of the recipe, if the player has them all
+pub(crate) fn recipe_ingredients(recipe: &Recipe, inventory: &[Object]) -> Option<Vec<usize>> {
+	let mut used: Vec<usize> = vec![];
+	for input in recipe.inputs {
+		let slot = (0..inventory.len())
+			.find(|&id| !used.contains(&id) && inventory[id].item == Some(*input))?;
+		used.push(slot);
+	}
+	Some(used)
+}
+
+pub(crate) fn craft_item(recipe: &Recipe, inventory: &mut Vec<Object>, messages: &mut MessageLog) -> bool {
+	// check everything is there before consuming anything
+	let mut slots = match recipe_ingredients(recipe, inventory) {
+		Some(slots) => slots,
+		None => {
+			messages.add(format!("You don't have what you need to make a {}.", recipe.name), colors::RED);
+			return false;
+		}
+	};
+
+	// remove from the back so the remaining indices stay valid
+	slots.sort_unstable_by(|a, b| b.cmp(a));
+	for slot in slots {
+		inventory.remove(slot);
+	}
+
+	let mut object = Object::new(0, 0, recipe.char, recipe.name, recipe.color, false);
+	object.item = Some(recipe.output);
+	messages.add(format!("You craft a {}!", recipe.name), colors::GREEN);
+	inventory.push(object);
+	true
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::open_arena;
+
+	fn carried(kind: Item) -> Object {
+		let mut item = Object::new(0, 0, '[', "gear", colors::WHITE, false);
+		item.item = Some(kind);
+		item.equipment = equipment_for(kind);
+		item
+	}
+
+	#[test]
+	fn upgrades_are_pointed_out() {
+		let mut worn = carried(Item::Shield);
+		worn.equipment.as_mut().unwrap().equipped = true;
+		let mut better = carried(Item::Shield);
+		better.equipment.as_mut().unwrap().defense_bonus += 1;
+
+		assert!(upgrade_hint(&better, &[worn.clone()]).is_some());
+		// the same again isn't an upgrade
+		assert!(upgrade_hint(&carried(Item::Shield), &[worn.clone()]).is_none());
+		assert!(upgrade_hint(&carried(Item::Sword), &[worn]).is_some(), "the sword hand is empty");
+		assert!(upgrade_hint(&carried(Item::Heal), &[]).is_none());
+	}
+
+	#[test]
+	fn equipment_counts_only_while_worn() {
+		let (mut objects, mut game) = open_arena();
+		let (power, max_hp) = (objects[PLAYER].power(&game), objects[PLAYER].max_hp(&game));
+		game.inventory.push(carried(Item::Sword));
+		game.inventory.push(carried(Item::Helmet));
+		assert_eq!(objects[PLAYER].power(&game), power, "carrying isn't wearing");
+
+		equip(0, &mut game);
+		equip(1, &mut game);
+		assert_eq!(objects[PLAYER].power(&game), power + SWORD_POWER_BONUS);
+		assert_eq!(objects[PLAYER].max_hp(&game), max_hp + HELMET_MAX_HP_BONUS);
+
+		// on and off again, and then dropped, leaves the player exactly as they were
+		unequip(0, &mut objects[PLAYER], &mut game);
+		equip(0, &mut game);
+		drop_item(1, &mut objects, &mut game);
+		drop_item(0, &mut objects, &mut game);
+		assert_eq!(objects[PLAYER].power(&game), power);
+		assert_eq!(objects[PLAYER].max_hp(&game), max_hp);
+		assert!(objects[PLAYER].fighter.unwrap().hp <= max_hp);
+	}
+
+	#[test]
+	fn loadouts_swap_back_and_forth() {
+		let (mut objects, mut game) = open_arena();
+		let power = objects[PLAYER].power(&game);
+		game.inventory.push(carried(Item::Sword));
+		game.inventory.push(carried(Item::Shield));
+		game.inventory[0].name = "sword".into();
+		game.inventory[1].name = "shield".into();
+		equip(0, &mut game);
+
+		// the backup loadout starts out empty
+		swap_loadout(&mut objects[PLAYER], &mut game);
+		assert_eq!(objects[PLAYER].power(&game), power);
+		equip(1, &mut game);
+		swap_loadout(&mut objects[PLAYER], &mut game);
+		assert_eq!(objects[PLAYER].power(&game), power + SWORD_POWER_BONUS);
+		assert!(!game.inventory[1].equipment.unwrap().equipped);
+
+		// the sword is gone from the main loadout, the shield still comes back
+		swap_loadout(&mut objects[PLAYER], &mut game);
+		game.inventory.remove(0);
+		swap_loadout(&mut objects[PLAYER], &mut game);
+		assert_eq!(objects[PLAYER].power(&game), power);
+		swap_loadout(&mut objects[PLAYER], &mut game);
+		assert!(game.inventory[0].equipment.unwrap().equipped);
+	}
+
+	#[test]
+	fn cursed_gear_sticks_until_the_curse_is_lifted() {
+		let (mut objects, mut game) = open_arena();
+		let power = objects[PLAYER].power(&game);
+		game.inventory.push(carried(Item::Sword));
+		equip(0, &mut game);
+		let mut cursed = carried(Item::Sword);
+		curse(cursed.equipment.as_mut().unwrap());
+		objects.push(cursed);
+
+		// it pushes the good sword out of the way
+		pick_item_up(1, &mut objects, &mut game);
+		assert!(!game.inventory[0].equipment.unwrap().equipped);
+		assert!(game.inventory[1].equipment.unwrap().equipped);
+		assert_eq!(objects[PLAYER].power(&game), power - CURSE_PENALTY);
+		assert!(!unequip(1, &mut objects[PLAYER], &mut game));
+		drop_item(1, &mut objects, &mut game);
+		assert_eq!(game.inventory.len(), 2, "can't be dropped either");
+
+		assert!(lift_curses(&mut game.inventory));
+		assert!(unequip(1, &mut objects[PLAYER], &mut game));
+		assert_eq!(objects[PLAYER].power(&game), power);
+	}
+
+	#[test]
+	fn unequip_all_takes_everything_off() {
+		let (mut objects, mut game) = open_arena();
+		let (power, defense) = (objects[PLAYER].power(&game), objects[PLAYER].defense(&game));
+		game.inventory.push(carried(Item::Sword));
+		game.inventory.push(carried(Item::Shield));
+		game.inventory.push(carried(Item::Helmet));
+		for id in 0..3 {
+			equip(id, &mut game);
+		}
+		unequip_all(&mut objects[PLAYER], &mut game);
+		assert!(game.inventory.iter().all(|item| !item.equipment.unwrap().equipped));
+		assert_eq!((objects[PLAYER].power(&game), objects[PLAYER].defense(&game)), (power, defense));
+	}
+}
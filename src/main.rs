@@ -1,14 +1,37 @@
 extern crate tcod;
 extern crate rand;
+extern crate serde;
+extern crate toml;
+
+mod items;
+mod map;
+mod object;
+mod render;
+mod replay;
 
 use std::cmp;
-use rand::Rng;
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Instant;
+use rand::{Rng, SeedableRng, StdRng};
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
 
 use tcod::console::*;
 use tcod::colors::{self, Color};
 use tcod::map::{Map as FovMap, FovAlgorithm};
+use tcod::pathfinding::AStar;
 use tcod::input::{self, Event, Key, Mouse};
 
+use items::*;
+use map::*;
+use object::*;
+use render::*;
+use replay::*;
+
 
 //Actual size of the window
 const SCREEN_WIDTH: i32 = 80;
@@ -18,6 +41,31 @@ const SCREEN_HEIGHT: i32 = 50;
 //FPS Maximum
 const FPS_LIMIT: i32 = 20;
 
+// where a run is kept between sessions, in the directory the game is started from
+const SAVE_FILE: &str = "savegame.json";
+
+
+// Fonts to try, in order of preference, in case the bundled one is missing
+const FONTS: &[(&str, FontLayout, FontType)] = &[
+	("arial10x10.png", FontLayout::Tcod, FontType::Greyscale),
+	("terminal10x10_gs_tc.png", FontLayout::Tcod, FontType::Greyscale),
+	("terminal.png", FontLayout::AsciiInCol, FontType::Default),
+];
+
+// A graphical tileset, loaded with --tileset=<png>, is a coloured 16-column font laid out
+// ASCII-in-row (CP437 order) with two extra rows of sprites for these glyphs, in this order.
+// Every glyph in monsters.toml and items.toml needs one
+const TILESET_SPRITES: &[char] = &[
+	'@', 'o', 'T', 's', 'W', 'V', '%', '!', '#', '?', '/', 'c', 'a', 'd', 'D', 'w',
+	'[', '(', ')', '>',
+];
+const TILESET_COLUMNS: i32 = 16;
+const TILESET_ROWS: i32 = 18;
+// the font's 256 characters fill the rows above the sprites
+const TILESET_SPRITE_ROW: i32 = 16;
+// the sprites get character codes after the 256 of the font
+const SPRITE_CODE: u32 = 256;
+
 
 //Map window size
 const MAP_WIDTH: i32 = 80;
@@ -29,47 +77,232 @@ const COLOR_DARK_WALL: Color = Color { r: 0, g: 0, b: 100 };
 const COLOR_LIGHT_WALL: Color = Color { r: 130, g: 110, b: 50 };
 const COLOR_DARK_GROUND: Color = Color { r: 50, g: 50, b: 150 };
 const COLOR_LIGHT_GROUND: Color = Color { r: 200, g: 180, b: 50 };
-
-
-//Room constraints
-const ROOM_MAX_SIZE: i32 = 10;
-const ROOM_MIN_SIZE: i32 = 6;
-const MAX_ROOMS: i32 = 30;
+const COLOR_DARK_CORRIDOR: Color = Color { r: 40, g: 40, b: 120 };
+const COLOR_LIGHT_CORRIDOR: Color = Color { r: 170, g: 150, b: 60 };
+const COLOR_DARK_CAVE: Color = Color { r: 60, g: 45, b: 110 };
+const COLOR_LIGHT_CAVE: Color = Color { r: 160, g: 130, b: 80 };
+const COLOR_DARK_SPECIAL_ROOM: Color = Color { r: 80, g: 40, b: 140 };
+const COLOR_LIGHT_SPECIAL_ROOM: Color = Color { r: 210, g: 160, b: 110 };
+const COLOR_DARK_SPIKES: Color = Color { r: 90, g: 30, b: 90 };
+const COLOR_LIGHT_SPIKES: Color = Color { r: 160, g: 60, b: 40 };
+const COLOR_DARK_NET: Color = Color { r: 60, g: 60, b: 30 };
+const COLOR_LIGHT_NET: Color = Color { r: 140, g: 130, b: 70 };
+const COLOR_DOOR: Color = Color { r: 160, g: 100, b: 40 };
+const COLOR_DANGER: Color = Color { r: 200, g: 60, b: 0 };
+// a breather drawing breath glows with it, so the player can see the fire coming
+const COLOR_WIND_UP: Color = Color { r: 255, g: 240, b: 120 };
+// the tile being aimed at, and what an area attack there would catch
+const COLOR_TARGET: Color = Color { r: 255, g: 200, b: 0 };
+// the debug overlay's monster paths and the edge of the player's sight
+const COLOR_DEBUG_PATH: Color = Color { r: 0, g: 90, b: 200 };
+const COLOR_DEBUG_FOV: Color = Color { r: 150, g: 0, b: 150 };
+const COLOR_TARGET_AREA: Color = Color { r: 150, g: 80, b: 0 };
+const TARGET_FLASH_MILLIS: u128 = 250;
+// how far remembered tiles are greyed out, from 0.0 (not at all) to 1.0 (fully grey)
+const FOG_DESATURATION: f32 = 0.6;
+
+
+//Room constraints, picked with --dungeon=sparse|normal|dense
+const NORMAL_DUNGEON: MapSettings = MapSettings {
+	generator: MapGenerator::Rooms, min_rooms: 1, max_rooms: 30, room_min_size: 6, room_max_size: 10,
+};
+const SPARSE_DUNGEON: MapSettings = MapSettings {
+	generator: MapGenerator::Rooms, min_rooms: 1, max_rooms: 10, room_min_size: 8, room_max_size: 14,
+};
+const DENSE_DUNGEON: MapSettings = MapSettings {
+	generator: MapGenerator::Rooms, min_rooms: 15, max_rooms: 60, room_min_size: 4, room_max_size: 8,
+};
+// how many extra tries make_map gets to reach min_rooms
+const ROOM_ATTEMPT_LIMIT: i32 = 1000;
+// how many times the BSP generator halves the map, and how lopsided a half can be
+const BSP_DEPTH: i32 = 5;
+const BSP_MAX_RATIO: f32 = 1.5;
 const MAX_ROOM_MONSTERS: i32 = 3;
 const MAX_ROOM_ITEMS: i32 = 2;
+// every so many levels down, rooms can hold one more monster or item
+const LEVELS_PER_EXTRA_MONSTER: i32 = 2;
+// the last level, where the dragon waits instead of the stairs
+const DRAGON_LEVEL: u32 = 10;
+const DRAGON_NAME: &str = "dragon";
+const LEVELS_PER_EXTRA_ITEM: i32 = 3;
+const MONSTER_LOOT_CHANCE: f32 = 0.3;
+// a slime with less HP than this is too small to split any more
+const SLIME_MIN_SPLIT_HP: i32 = 4;
 
 
 // Field of View
 const FOV_ALGO: FovAlgorithm = FovAlgorithm::Basic;
 const FOV_LIGHT_WALLS: bool = true;
 const TORCH_RADIUS: i32 = 10;
+// false for "true darkness": tiles out of view go black instead of being remembered
+const TILE_MEMORY: bool = true;
 
-// GUI Panel
+// GUI Panel, the rest of its layout is worked out from the window size
 const BAR_WIDTH: i32 = 20;
 const PANEL_HEIGHT: i32 = 7;
-const PANEL_Y: i32 = SCREEN_HEIGHT - PANEL_HEIGHT;
 const INVENTORY_WIDTH: i32 = 50;
+const JOURNAL_WIDTH: i32 = 60;
+const CHARACTER_SCREEN_WIDTH: i32 = 30;
+// wide enough for a journal line, which the end of run screens list
+const DEATH_SCREEN_WIDTH: i32 = 50;
+const VICTORY_SCREEN_WIDTH: i32 = 50;
+const REPLAY_SCREEN_WIDTH: i32 = 50;
+// how many of the newest journal entries the end of run screens show
+const SUMMARY_JOURNAL_ENTRIES: usize = 20;
+const QUIT_MENU_WIDTH: i32 = 24;
+const MAIN_MENU_WIDTH: i32 = 24;
+// each character of the minimap stands for a square this many tiles across
+const MINIMAP_SCALE: i32 = 4;
+const MENU_PAGE_SIZE: usize = 26;
+// items the player can carry at the start; bags of holding add to it
+const INVENTORY_CAPACITY: usize = 26;
 
 // Message Bar
-const MSG_X: i32 = BAR_WIDTH + 2;
-const MSG_WIDTH: i32 = SCREEN_WIDTH - BAR_WIDTH - 2;
-const MSG_HEIGHT: usize = PANEL_HEIGHT as usize -1;
+const MSG_LOG_CAPACITY: usize = 100;
+// collapse repeats within one turn into a single "(x3)" line; false keeps the full log
+const AGGREGATE_MESSAGES: bool = true;
+const PEEK_HEIGHT: i32 = 15;
+// the 'p' message history window
+const HISTORY_WIDTH: i32 = 60;
+const HISTORY_HEIGHT: i32 = 30;
 
 // Spells
 const HEAL_AMOUNT: i32 = 4;
+const GREATER_HEAL_AMOUNT: i32 = 12;
+// life drain takes max HP, but never more than MAX_DRAIN in total
+const DRAIN_AMOUNT: i32 = 2;
+const MAX_DRAIN: i32 = 10;
 const LIGHTNING_DAMAGE: i32 = 20;
 const LIGHTNING_RANGE: i32 = 5;
 const CONFUSE_RANGE: i32 = 8;
 const CONFUSE_NUM_TURNS: i32 = 10;
 const FIREBALL_RADIUS: i32 = 3;
 const FIREBALL_DAMAGE: i32 = 12;
+const FIRE_FLASK_RANGE: i32 = 6;
+const FIRE_FLASK_RADIUS: i32 = 1;
+const FIRE_NUM_TURNS: i32 = 5;
+const FIRE_DAMAGE: i32 = 3;
+// spikes hurt whatever ends its turn on them
+const SPIKE_DAMAGE: i32 = 2;
+const SPIKE_ROOM_CHANCE: f32 = 0.3;
+const MAX_ROOM_SPIKES: i32 = 4;
+
+// how likely a doorway where a tunnel meets a room gets a door
+const DOOR_CHANCE: f32 = 0.5;
+
+// the floor each room gets, None being the plain one
+const ROOM_STYLES: &[(Option<TileType>, f32)] = &[
+	(None, 70.0),
+	(Some(TileType::Cave), 20.0),
+	(Some(TileType::SpecialRoom), 10.0),
+];
+
+// how rooms get joined up, picked separately for each connection
+const CORRIDOR_STYLES: &[(Corridor, f32)] = &[
+	(Corridor::Straight, 60.0),
+	(Corridor::Winding, 20.0),
+	(Corridor::Wide, 10.0),
+	(Corridor::Diagonal, 10.0),
+];
+const WINDING_WANDER_CHANCE: f32 = 0.3;
+const WINDING_STEP_LIMIT: i32 = 400;
+const DIG_RANGE: i32 = 8;
+const NET_RANGE: i32 = 4;
+const NET_HOLD_TURNS: i32 = 4;
+const THROW_RANGE: i32 = 6;
+const BAG_OF_HOLDING_SLOTS: usize = 10;
+const SWORD_POWER_BONUS: i32 = 3;
+const SHIELD_DEFENSE_BONUS: i32 = 1;
+const HELMET_MAX_HP_BONUS: i32 = 5;
+// how much of the gear lying around is cursed, and what a cursed piece takes instead of giving
+const CURSE_CHANCE: f32 = 0.15;
+const CURSE_PENALTY: i32 = 2;
+const SUMMON_NUM_TURNS: i32 = 30;
+const DETECT_NUM_TURNS: i32 = 20;
+const SEE_INVISIBLE_NUM_TURNS: i32 = 30;
+const ALLY_SIGHT: f32 = 8.0;
+// how far monsters see the player, unless monsters.toml says otherwise
+const MONSTER_SIGHT_RADIUS: f32 = 8.0;
+// once it has spotted the player, a monster keeps after them this many turns out of sight
+const MONSTER_MEMORY_TURNS: i32 = 5;
+// guardians won't chase the player further than this from their post
+const LEASH_RADIUS: f32 = 6.0;
+// monsters further than this along the path just head straight for the player
+const ASTAR_MAX_PATH: i32 = 25;
+const ASTAR_DIAGONAL_COST: f32 = 1.41;
+// healers mend one nearby monster a turn instead of attacking
+const HEALER_RADIUS: f32 = 5.0;
+const HEALER_AMOUNT: i32 = 3;
+// how much fleeing monsters care about open space around a step, versus distance from the player
+const FLEE_OPEN_BONUS: f32 = 0.25;
+// breathers wind up for a turn, then set a line of floor alight
+const BREATH_RANGE: f32 = 6.0;
+const BREATH_CHANCE: f32 = 0.3;
+const BREATH_BURN_TURNS: i32 = 2;
+
+// Combat, set COMBAT_VARIANCE to false for fully predictable damage
+const COMBAT_VARIANCE: bool = true;
+const DAMAGE_VARIANCE: i32 = 1;
+const CRITICAL_CHANCE: f32 = 0.05;
+
+// Mana
+const MAX_MANA: i32 = 20;
+const MANA_REGEN_INTERVAL: u32 = 5;
+
+// Slowly getting hit points back, one every REGEN_INTERVAL turns
+const REGEN_ENABLED: bool = true;
+const REGEN_INTERVAL: u32 = 10;
+
+// Dynamic difficulty: after each level it steps down if the player nearly died there, and up if they
+// got through it quickly and hardly hurt. It scales how many monsters new levels get and how tough
+const DYNAMIC_DIFFICULTY: bool = true;
+const DIFFICULTY_STEP: f32 = 0.05;
+const MIN_DIFFICULTY: f32 = 0.8;
+const MAX_DIFFICULTY: f32 = 1.25;
+const STRUGGLING_HP_FRACTION: f32 = 0.25;
+const CRUISING_HP_FRACTION: f32 = 0.75;
+const QUICK_LEVEL_TURNS: u32 = 300;
+
+// Each New Game+ after a victory makes levels this much harder again, on top of the above
+const NG_PLUS_DIFFICULTY: f32 = 0.25;
+
+// after dying, look around as a ghost before the game ends
+const GHOST_MODE: bool = true;
+
+// Walking into a tile tries these in order; none of them applying (a wall) still uses the turn
+const BUMP_ORDER: &[Bump] = &[Bump::Attack, Bump::Swap, Bump::Loot, Bump::Door, Bump::Move];
+
+// Stamina: attacking tires the player out; false keeps unlimited attacks
+const STAMINA_ENABLED: bool = false;
+const MAX_STAMINA: i32 = 10;
+const ATTACK_STAMINA: i32 = 3;
+
+// Resting
+const REST_TURNS: i32 = 20;
+
+// '>' works from this close to the stairs, not only standing on them
+const QUICK_DESCEND_RANGE: f32 = 1.5;
+
+// Low health warning, set LOW_HP_CONFIRM to false to never ask before a risky step
+const LOW_HP_FRACTION: f32 = 0.2;
+const LOW_HP_FLASH_MILLIS: u128 = 500;
+const LOW_HP_CONFIRM: bool = true;
+
+// Benchmark
+const BENCH_MONSTERS: usize = 200;
+const BENCH_TURNS: usize = 100;
+const BENCH_FRAMES: usize = 200;
+
+// Hunger (off by default)
+const HUNGER_ENABLED: bool = false;
+const MAX_SATIATION: i32 = 1000;
+const HUNGRY_SATIATION: i32 = 150;
+const FOOD_AMOUNT: i32 = 400;
+const FOOD_CHANCE: f32 = 0.2;
+const STARVATION_DAMAGE: i32 = 1;
 
 const PLAYER: usize = 0;
 
-type Map = Vec<Vec<Tile>>;
-
-type Messages = Vec<(String, Color)>;
-
 /////////////////////////////
 //////
 //////    Structs, etc.
@@ -82,521 +315,564 @@ struct Tcod {
 	panel: Offscreen,
 	fov: FovMap,
 	mouse: Mouse,
+	look_cursor: Option<(i32, i32)>, // keyboard-look position, or None to look with the mouse
+	tileset: bool, // objects are drawn with tileset sprites instead of their characters
+	layout: Layout,
+	minimap: bool,
+	input: Input, // live, or being recorded or played back
+}
+
+// Where the parts of the GUI panel go on the screen
+struct Layout {
+	panel_y: i32,
+	panel_width: i32,
+	panel_height: i32,
+	bar_width: i32,
+	msg_x: i32,
+	msg_width: i32,
+	msg_height: i32,
+}
+
+impl Layout {
+	pub fn new(screen_width: i32, screen_height: i32) -> Self {
+		// the bars take the left of the panel and the messages get the rest
+		let panel_height = cmp::min(PANEL_HEIGHT, screen_height);
+		let bar_width = cmp::min(BAR_WIDTH, screen_width / 3);
+		let msg_x = bar_width + 2;
+		Layout {
+			panel_y: screen_height - panel_height,
+			panel_width: screen_width,
+			panel_height,
+			bar_width,
+			msg_x,
+			msg_width: screen_width - msg_x,
+			msg_height: panel_height - 1,
+		}
+	}
 }
 
 struct Game {
 	map: Map,
-	log: Messages,
+	log: MessageLog,
 	inventory: Vec<Object>,
+	inventory_capacity: usize,
+	rest_turns: i32,
+	satiation: i32,
+	stats: GameStats,
+	mana: i32,
+	stamina: i32,
+	max_mana: i32,
+	spells: Vec<Spell>,
+	difficulty: f32, // 1.0 as designed; see DYNAMIC_DIFFICULTY
+	hp_low: f32, // the lowest the player's HP has been on this level, as a fraction of the max
+	level_start_turn: u32,
+	// the names of the gear in each of the two loadouts, and which one is worn
+	loadouts: [Vec<String>; 2],
+	loadout: usize,
+	ng_plus: u32, // how many times the player has won and started over, see new_game_plus
+	tutorial: Option<Tutorial>,
+	practice_spawn: Option<(i32, i32)>, // where a practice run respawns the player; None means permadeath
+	detect_turns: i32, // monsters are shown through walls while this is above zero
+	see_invisible_turns: i32,
+	last_command: Option<Command>,
+	low_hp_warned: Option<Command>, // the move that was refused because of low health
+	map_settings: MapSettings,
+	debug: bool, // started with --debug, which turns on the debug keys
+	debug_overlay: bool, // draw monster paths and the FOV boundary, toggled with F10
+	content: Content,
+	journal: Vec<JournalEntry>,
+	dungeon_level: u32,
+	seed: u64, // every level is generated from this and its depth
+	rng: StdRng, // combat rolls, so a seed also replays the same fights
+	pathing: FovMap, // where monsters can walk this turn, see refresh_pathing
 }
 
-#[derive(Clone, Copy, Debug)]
-struct Rect {
-	x1: i32,
-	x2: i32,
-	y1: i32,
-	y2: i32,
+// How the run has gone so far, for the death and victory screens
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+struct GameStats {
+	kills: u32,
+	turns: u32, // only the ones the player actually spent
+	deepest_level: u32,
+	#[serde(default)]
+	won: bool, // the dragon is dead
 }
 
-impl Rect {
-	pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
-		Rect{ x1: x, y1: y, x2: x + w, y2: y + h}
-	}
+// Something worth remembering about the run, kept apart from the message log
+#[derive(Clone, Serialize, Deserialize)]
+struct JournalEntry {
+	turn: u32,
+	text: String,
+}
 
-	pub fn center(&self) -> (i32, i32) {
-		let center_x = (self.x1 + self.x2) / 2;
-		let center_y = (self.y1 + self.y2) / 2;
-		(center_x, center_y)
-	}
+fn add_journal_entry<T: Into<String>>(game: &mut Game, text: T) {
+	let turn = game.stats.turns;
+	game.journal.push(JournalEntry { turn, text: text.into() });
+}
 
-	pub fn intersects_with(&self, other: &Rect) -> bool {
-		//returns true if this rectangle intersects with another one
-		(self.x1 <= other.x2) && (self.x2 >= other.x1) &&
-			(self.y1 <= other.y2) && (self.y2 >= other.y1)
+// The end of run screen: how it ended, the stats and how the run went, from the journal
+fn run_summary(headline: &str, game: &Game) -> String {
+	let mut text = format!("{}\n\nMonsters killed: {}\nTurns taken: {}\nDeepest level: {}\n",
+		headline, game.stats.kills, game.stats.turns, game.stats.deepest_level);
+	if game.ng_plus > 0 {
+		text += &format!("New Game+: {}\n", game.ng_plus);
 	}
-
+	text += "\n";
+	let shown = game.journal.len().saturating_sub(SUMMARY_JOURNAL_ENTRIES);
+	for entry in &game.journal[shown..] {
+		text += &format!("Turn {:>5}: {}\n", entry.turn, entry.text);
+	}
+	text + "\nPress any key."
 }
 
-#[derive(Clone, Copy, Debug)]
-struct Tile {
-	blocked: bool,
-	block_sight: bool,
-	explored: bool,
+struct MessageLog {
+	messages: Vec<(String, Color)>,
+	capacity: usize,
+	turn_start: usize, // index of the first message of the current turn
 }
 
-impl Tile {
-	pub fn empty() -> Self {
-		Tile{ blocked: false, explored: false, block_sight: false }
+impl MessageLog {
+	pub fn new(capacity: usize) -> Self {
+		MessageLog { messages: vec![], capacity, turn_start: 0 }
 	}
 
-	pub fn wall() -> Self {
-		Tile{ blocked: true, explored: false, block_sight: true }
+	pub fn start_turn(&mut self) {
+		self.turn_start = self.len();
 	}
-}
 
-#[derive(Debug)]
-struct Object {
-	x: i32,
-	y: i32,
-	char: char,
-	color: Color,
-	name: String,
-	blocks: bool,
-	alive: bool,
-	fighter: Option<Fighter>,
-	ai: Option<Ai>,
-	item: Option<Item>,
-}
+	pub fn add<T: Into<String>>(&mut self, message: T, color: Color) {
+		let message = message.into();
+		if AGGREGATE_MESSAGES {
+			// already said this turn, so count it instead of adding another line
+			for line in self.messages[self.turn_start..].iter_mut() {
+				if let Some(count) = repeat_count(&line.0, &message) {
+					line.0 = format!("{} (x{})", message, count + 1);
+					return;
+				}
+			}
+		}
 
-impl Object {
-	pub fn new(x: i32, y: i32, char: char, name: &str, color: Color, blocks: bool) -> Self {
-		Object {
-			x: x,
-			y: y,
-			char: char,
-			color: color,
-			name: name.into(),
-			blocks: blocks,
-			alive: false,
-			fighter: None,
-			ai: None,
-			item: None,
+		// if the buffer is full, remove the first message to make room for the new one
+		if self.len() == self.capacity {
+			self.messages.remove(0);
+			self.turn_start = self.turn_start.saturating_sub(1);
 		}
+		// add the new line as a tuple with the text and the color
+		self.messages.push((message, color));
 	}
 
-	// set the color and draw the character that represents this object at its position
-	pub fn draw(&self, con: &mut Console) {
-		con.set_default_foreground(self.color);
-		con.put_char(self.x, self.y, self.char, BackgroundFlag::None);
+	// the newest `n` messages, oldest first
+	pub fn iter_tail(&self, n: usize) -> std::slice::Iter<'_, (String, Color)> {
+		let start = self.len().saturating_sub(n);
+		self.messages[start..].iter()
 	}
 
-	pub fn pos(&self) -> (i32, i32) {
-		(self.x, self.y)
+	pub fn len(&self) -> usize {
+		self.messages.len()
 	}
+}
 
-	pub fn set_pos(&mut self, x: i32, y: i32) {
-		self.x = x;
-		self.y = y;
-	}
-	
-	pub fn distance_to(&self, other: &Object) -> f32 {
-		let dx = other.x - self.x;
-		let dy = other.y - self.y;
-		((dx.pow(2) + dy.pow(2)) as f32).sqrt()
+// How many times `message` has been logged in `line`, if it is that message at all
+fn repeat_count(line: &str, message: &str) -> Option<u32> {
+	if line == message {
+		return Some(1);
 	}
+	line.strip_prefix(message)?
+		.strip_prefix(" (x")?
+		.strip_suffix(')')?
+		.parse().ok()
+}
 
-	pub fn distance(&self, x: i32, y: i32) -> f32 {
-		(((x - self.x).pow(2) + (y - self.y).pow(2)) as f32).sqrt()
-	}
+// One-shot hints for first-time players, only used when started with --tutorial
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum TutorialHint {
+	Start,
+	MonsterSeen,
+	ItemSeen,
+	ItemPickedUp,
+	Wounded,
+	CorpseSeen,
+}
 
-	pub fn take_damage(&mut self, damage: i32, messages: &mut Messages) {
-		// apply damage if possible
-		if let Some(fighter) = self.fighter.as_mut() {
-			if damage > 0 {
-				fighter.hp -= damage;
-			}
+impl TutorialHint {
+	fn text(self) -> &'static str {
+		match self {
+			TutorialHint::Start => "Use the arrow keys to move. Walk into a monster to attack it.",
+			TutorialHint::MonsterSeen => "A monster! Walk into it to attack, or back away down a corridor.",
+			TutorialHint::ItemSeen => "There's an item nearby. Stand on it and press 'g' to pick it up.",
+			TutorialHint::ItemPickedUp => "Press 'i' to use an item and 'd' to drop one.",
+			TutorialHint::Wounded => "You are hurt. Press 'r' to rest when no enemies are around.",
+			TutorialHint::CorpseSeen => "Monsters may leave something behind. Stand on the remains and press 'l' to loot.",
 		}
+	}
+}
 
-		// check for death, call the death function
-		if let Some(fighter) = self.fighter {
-			if fighter.hp <= 0 {
-				self.alive = false;
-				fighter.on_death.callback(self, messages);
-			}
+struct Tutorial {
+	shown: Vec<TutorialHint>,
+}
+
+impl Tutorial {
+	// show a hint the first time it comes up, and never again
+	fn hint(&mut self, hint: TutorialHint, messages: &mut MessageLog) {
+		if !self.shown.contains(&hint) {
+			self.shown.push(hint);
+			messages.add(hint.text(), colors::LIGHT_GREEN);
 		}
 	}
+}
 
-	pub fn heal(&mut self, amount: i32) {
-		if let Some(ref mut fighter) = self.fighter {
-			fighter.hp += amount;
-			if fighter.hp > fighter.max_hp {
-				fighter.hp = fighter.max_hp;
-			}
+// How many rooms make_map tries to place, and how big they are
+#[derive(Clone, Copy, Debug)]
+struct MapSettings {
+	generator: MapGenerator,
+	min_rooms: i32,
+	max_rooms: i32,
+	room_min_size: i32,
+	room_max_size: i32,
+}
+
+impl MapSettings {
+	// the preset named on the command line, or the normal one, made with the generator asked for
+	fn from_args() -> Result<Self, String> {
+		let mut settings = match env::args().find(|arg| arg.starts_with("--dungeon=")) {
+			None => NORMAL_DUNGEON,
+			Some(arg) => match &arg["--dungeon=".len()..] {
+				"sparse" => SPARSE_DUNGEON,
+				"normal" => NORMAL_DUNGEON,
+				"dense" => DENSE_DUNGEON,
+				other => return Err(format!("Unknown dungeon type '{}', expected sparse, normal or dense.", other)),
+			},
+		};
+		if let Some(arg) = env::args().find(|arg| arg.starts_with("--generator=")) {
+			settings.generator = match &arg["--generator=".len()..] {
+				"rooms" => MapGenerator::Rooms,
+				"bsp" => MapGenerator::Bsp,
+				other => return Err(format!("Unknown generator '{}', expected rooms or bsp.", other)),
+			};
 		}
+		Ok(settings)
 	}
 
-	pub fn attack(&mut self, target: &mut Object, messages: &mut Messages) {
-		// a simple damage formula
-		let damage = self.fighter.map_or(0, |f| f.power) - target.fighter.map_or(0, |f| f.defense);
-		if damage > 0 {
-			// target takes dmaage
-			message(messages, format!("{} attacks {} for {} hit points.", self.name, target.name, damage), colors::WHITE);
-			target.take_damage(damage, messages);
-		} else {
-			message(messages, format!("{} attacks {} but it has no effect!", self.name, target.name), colors::WHITE);
+	fn validate(&self) -> Result<(), String> {
+		// a room needs floor inside its walls, and has to fit on the map
+		if self.room_min_size < 3 || self.room_min_size > self.room_max_size {
+			return Err(format!("Rooms can't be between {} and {} tiles wide.", self.room_min_size, self.room_max_size));
+		}
+		if self.room_max_size >= MAP_WIDTH || self.room_max_size >= MAP_HEIGHT {
+			return Err(format!("Rooms {} tiles wide don't fit on the map.", self.room_max_size));
+		}
+		// the player needs a room to start in
+		if self.min_rooms < 1 || self.min_rooms > self.max_rooms {
+			return Err(format!("Can't make between {} and {} rooms.", self.min_rooms, self.max_rooms));
+		}
+		// rooms can't overlap, so leave plenty of space spare
+		let area = self.min_rooms * (self.room_min_size + 1).pow(2);
+		if area > MAP_WIDTH * MAP_HEIGHT / 2 {
+			return Err(format!("{} rooms of size {} won't fit on the map.", self.min_rooms, self.room_min_size));
 		}
+		Ok(())
 	}
 }
 
+// A command the player can repeat with '`'
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Command {
+	Move(i32, i32),
+	Rest,
+	Wait,
+}
+
+// What walking into a tile can do
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Bump {
+	Attack, // a hostile monster
+	Swap,   // trade places with an ally
+	Loot,   // take what's on a corpse
+	Door,   // open a closed door
+	Move,   // step onto the tile
+}
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 enum PlayerAction {
 	TookTurn,
 	DidntTakeTurn,
 	Exit,
+	NewGame,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum DeathCallBack {
-	Player,
-	Monster,
+// Monster and item definitions, loaded from monsters.toml and items.toml
+#[derive(Clone)]
+struct Content {
+	monsters: Vec<MonsterDef>,
+	items: Vec<ItemDef>,
 }
 
-impl DeathCallBack {
-	fn callback(self, object: &mut Object, messages: &mut Messages) {
-		use DeathCallBack::*;
-		let callback: fn(&mut Object, &mut Messages) = match self {
-			Player => player_death,
-			Monster => monster_death,
-		};
-		callback(object, messages);
-	}
-}
-
-#[derive(Clone, Copy, Debug, PartialEq)]
-struct Fighter {
-	max_hp: i32,
+#[derive(Clone, Deserialize)]
+struct MonsterDef {
+	name: String,
+	glyph: char,
+	color: [u8; 3],
 	hp: i32,
 	defense: i32,
 	power: i32,
-	on_death: DeathCallBack,
-}
-
-#[derive(Clone, Debug, PartialEq)]
-enum Ai {
-	Basic,
-	Confused{previous_ai: Box<Ai>, num_turns: i32},
+	attack_verb: Option<String>,
+	ai: MonsterAi,
+	#[serde(default)]
+	splits: bool,
+	#[serde(default)]
+	invisible: bool,
+	#[serde(default)]
+	drains: bool,
+	#[serde(default)]
+	immunities: Vec<Effect>,
+	#[serde(default)]
+	flee_at: f32,
+	sight_radius: Option<f32>,
+	#[serde(default)]
+	range: f32,
+	weight: Weight,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum Item {
-	Heal,
-	Lightning,
-	Confuse,
-	Fireball,
-
+// How often something turns up: the same on every level, or changing with depth
+#[derive(Clone, Deserialize)]
+#[serde(untagged)]
+enum Weight {
+	Flat(f32),
+	ByLevel(Vec<Transition>),
 }
 
-enum UseResult {
-	UsedUp,
-	Cancelled,
+// From this dungeon level on (until the next transition), the weight is this value
+#[derive(Clone, Copy, Deserialize)]
+struct Transition {
+	level: u32,
+	value: f32,
 }
 
-/////////////////////
-/////
-/////  Functions
-/////
-/////////////////////
-
-fn player_death(player: &mut Object, messages: &mut Messages) {
-	// the game ends
-	message(messages, "You died!", colors::RED);
-
-	// for added effect, transform player into a corpse
-	player.char = '%';
-	player.color = colors::DARK_RED;
-}
-
-fn monster_death(monster: &mut Object, messages: &mut Messages) {
-	// transform the monster into a corpse
-	// Doesn't block, cant be attacked, doesn't move
-	message(messages, format!("{} is dead!", monster.name), colors::ORANGE);
-	monster.char = '%';
-	monster.color = colors::DARK_RED;
-	monster.blocks = false;
-	monster.fighter = None;
-	monster.ai = None;
-	monster.name = format!("remains of {}", monster.name);
-}
-
-fn mut_two<T>(first_index: usize, second_index: usize, items: &mut [T]) -> (&mut T, &mut T) {
-	assert!(first_index != second_index);
-	let split_at_index = cmp::max(first_index, second_index);
-	let (first_slice, second_slice) = items.split_at_mut(split_at_index);
-	if first_index < second_index {
-		(&mut first_slice[first_index], &mut second_slice[0])
-	} else {
-		(&mut second_slice[0], &mut first_slice[second_index])
+impl Weight {
+	fn at(&self, level: u32) -> f32 {
+		match self {
+			Weight::Flat(weight) => *weight,
+			Weight::ByLevel(table) => from_dungeon_level(table, level),
+		}
 	}
-}
 
-fn place_objects(room: Rect, map: &Map, objects: &mut Vec<Object>) {
-	// choose random number of monsters
-	let num_monsters = rand::thread_rng().gen_range(0, MAX_ROOM_MONSTERS + 1);
-
-
-		for _ in 0..num_monsters {
-			// choose random location for the monster
-			let x = rand::thread_rng().gen_range(room.x1 + 1, room.x2);
-			let y = rand::thread_rng().gen_range(room.y1 + 1, room.y2);
-		
-			// Only place if the tile is not blocked
-			if !is_blocked(x, y, map, objects) {
-				let mut monster = if rand::random::<f32>() < 0.8 { // 80% chance of getting an orc
-					// create an orc
-					
-					let mut orc = Object::new(x, y, 'o', "orc", colors::DESATURATED_GREEN, true);
-					orc.fighter = Some(Fighter{max_hp: 10, hp: 10, defense: 0, power: 3, on_death: DeathCallBack::Monster});
-					orc.ai = Some(Ai::Basic);
-					orc
-				} else {
-					// create a troll
-					let mut troll = Object::new(x, y, 'T', "troll", colors::DARKER_GREEN, true);
-					troll.fighter = Some(Fighter{max_hp: 16, hp: 16, defense: 1, power: 4, on_death: DeathCallBack::Monster});
-					troll.ai = Some(Ai::Basic);
-					troll
-				};
-		
-			monster.alive = true;
-			objects.push(monster);
-		}
-	}
-
-	// Choose random number of items
-	let num_items = rand::thread_rng().gen_range(0, MAX_ROOM_ITEMS + 1);
-
-	for _ in 0..num_items {
-		// choose random spot for this item
-		let x = rand::thread_rng().gen_range(room.x1 + 1, room.x2);
-		let y = rand::thread_rng().gen_range(room.y1 + 1, room.y2);
-
-		// only place item if the tile is not blocked
-		if !is_blocked(x, y, map, objects) {
-			let dice = rand::random::<f32>();
-			let item = if dice < 0.7 {
-			// create a healing potion (70% chance)
-			let mut object = Object::new(x, y, '!', "healing potion",
-							colors::VIOLET, false);
-			object.item = Some(Item::Heal);
-			object
-			} else if dice < 0.7 + 0.1 {
-			// create a lightning bolt scroll
-			let mut object = Object::new(x, y, '#', "scroll of lighting bolt",
-							colors::LIGHT_YELLOW, false);
-			object.item = Some(Item::Lightning);
-			object
-		} else if dice < 0.7 + 0.1 + 0.1{
-			// create a fireball scroll (10% chance)
-			let mut object = Object::new(x, y, '#', "scroll of fireball",
-					colors::LIGHT_YELLOW, false);
-			object.item = Some(Item::Fireball);
-			object
-			} else {
-				// create a confusion scroll (10% chance)
-				let mut object = Object::new(x, y, '#', "scroll of confusion",
-							colors::LIGHT_YELLOW, false);
-				object.item = Some(Item::Confuse);
-				object
-			};
-			objects.push(item);
+	// the deepest level at which this weight still changes
+	fn last_change(&self) -> u32 {
+		match self {
+			Weight::Flat(_) => 1,
+			Weight::ByLevel(table) => table.iter().map(|transition| transition.level).max().unwrap_or(1),
 		}
 	}
 }
 
-fn pick_item_up(object_id: usize, objects: &mut Vec<Object>, inventory: &mut Vec<Object>,
-				messages: &mut Messages) {
-	if inventory.len() >= 26 {
-		message(messages,
-			format!("Your inventory is full, cannot pick up {}.", objects[object_id].name), colors::RED);
-	} else {
-		let item = objects.swap_remove(object_id);
-		message(messages, format!("You picked up a {}!", item.name), colors::GREEN);
-		inventory.push(item);
-	}
+// The value of the last transition reached at this level, or 0 before the first one
+fn from_dungeon_level(table: &[Transition], level: u32) -> f32 {
+	table.iter()
+		.rev()
+		.find(|transition| level >= transition.level)
+		.map_or(0.0, |transition| transition.value)
 }
 
-fn move_towards(id: usize, target_x: i32, target_y: i32, map: &Map, objects: &mut [Object]){
-	// vector from this object to the target, and distance
-	let dx = target_x - objects[id].x;
-	let dy = target_y - objects[id].y;
-	let distance = ((dx.pow(2) + dy.pow(2)) as f32).sqrt();
-
-	// normalise it to length 1 (preservind direction), then round it and
-	// convert to integer so the movement is restricted to the grid
-	let dx = (dx as f32 /distance).round() as i32;
-	let dy = (dy as f32/ distance).round() as i32;
-	move_by(id, dx, dy, map, objects);
+#[derive(Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MonsterAi {
+	Basic,
+	Guardian,
+	Healer,
+	Breather,
+	Archer,
 }
 
-fn target_monster(tcod: &mut Tcod, objects: &[Object], game: &mut Game, max_range: Option<f32>) -> Option<usize> {
-	loop {
-		match target_tile(tcod, objects, game, max_range) {
-			Some((x, y)) => {
-				// return the first clicked monster, otherwise continue loop
-				for (id, obj) in objects.iter().enumerate() {
-					if obj.pos() == (x, y) && obj.fighter.is_some() && id != PLAYER {
-						return Some(id)
-					}
-				}
-			}
-			None => return None,
-		}
+impl MonsterDef {
+	fn spawn(&self, x: i32, y: i32) -> Object {
+		let [r, g, b] = self.color;
+		let mut monster = Object::new(x, y, self.glyph, &self.name, Color::new(r, g, b), true);
+		monster.alive = true;
+		monster.fighter = Some(Fighter{max_hp: self.hp, hp: self.hp, defense: self.defense,
+						power: self.power, on_death: DeathCallBack::Monster, range: self.range});
+		monster.ai = Some(match self.ai {
+			MonsterAi::Basic => Ai::Basic,
+			// guardians watch over the spot they were found on
+			MonsterAi::Guardian => Ai::Guardian{post_x: x, post_y: y},
+			MonsterAi::Healer => Ai::Healer,
+			MonsterAi::Breather => Ai::Breather{aim: None},
+			MonsterAi::Archer => Ai::Archer,
+		});
+		monster.attack_verb = self.attack_verb.clone();
+		monster.splits = self.splits;
+		monster.invisible = self.invisible;
+		monster.drains = self.drains;
+		monster.immunities = self.immunities.clone();
+		monster.flee_at = self.flee_at;
+		monster.sight_radius = self.sight_radius.unwrap_or(MONSTER_SIGHT_RADIUS);
+		monster
 	}
 }
 
-
-fn ai_take_turn(monster_id: usize, game: &mut Game, objects: &mut [Object],
-		fov_map: &FovMap) {
-	use Ai::*;
-	if let Some(ai) = objects[monster_id].ai.take() {
-		let new_ai = match ai {
-			Basic => ai_basic(monster_id, objects, game, fov_map),
-			Confused{previous_ai, num_turns} => ai_confused(
-				monster_id, objects, game, previous_ai, num_turns)
-		};
-		objects[monster_id].ai = Some(new_ai);
-	}
+#[derive(Clone, Deserialize)]
+struct ItemDef {
+	name: String,
+	glyph: char,
+	color: [u8; 3],
+	kind: Item,
+	weight: Weight,
 }
 
-fn ai_basic(monster_id: usize, objects: &mut [Object], game: &mut Game, fov_map: &FovMap) -> Ai {
-	let (monster_x, monster_y) = objects[monster_id].pos();
-	if fov_map.is_in_fov(monster_x, monster_y) {
-		if objects[monster_id].distance_to(&objects[PLAYER]) >= 2.0 {
-			// move towards player if far away
-			let (player_x, player_y) = objects[PLAYER].pos();
-			move_towards(monster_id, player_x, player_y, &game.map, objects);
-		} else if objects[PLAYER].fighter.map_or(false, |f| f.hp > 0) {
-			// close enough, attack if player still alive
-			let (monster, player) = mut_two(monster_id, PLAYER, objects);
-			monster.attack(player, &mut game.log);
-		}
+impl ItemDef {
+	fn spawn(&self, x: i32, y: i32) -> Object {
+		let [r, g, b] = self.color;
+		let mut object = Object::new(x, y, self.glyph, &self.name, Color::new(r, g, b), false);
+		object.item = Some(self.kind);
+		object.equipment = equipment_for(self.kind);
+		object
 	}
-	Ai::Basic
 }
 
-fn ai_confused(monster_id: usize, objects: &mut [Object], game: &mut Game,
-				previous_ai: Box<Ai>, num_turns: i32) -> Ai {
-	if num_turns >= 0 { // still confused
-		// move in a random direction and decrease turns
-		move_by(monster_id,
-			rand::thread_rng().gen_range(-1, 2),
-			rand::thread_rng().gen_range(-1, 2),
-			&game.map,
-			objects);
-		Ai::Confused{previous_ai: previous_ai, num_turns: num_turns - 1}
-	} else { // restore the previous AI(this one will be deleted)
-		message(&mut game.log, format!("The {} is no longer confused!",
-									objects[monster_id].name),
-							colors::RED);
-		*previous_ai
-	}
+// the layout of the data files: a list of [[monster]] or [[item]] tables
+#[derive(Deserialize)]
+struct MonsterFile {
+	monster: Vec<MonsterDef>,
 }
 
-fn is_blocked(x: i32, y: i32, map: &Map, objects: &[Object]) -> bool {
-	// first test the map tile
-	if map[x as usize][y as usize].blocked {
-		return true;
-	}
-
-	// now check for any blocking objects
-	objects.iter().any(|object| {
-		object.blocks && object.pos() == (x, y)
-	})
+#[derive(Deserialize)]
+struct ItemFile {
+	item: Vec<ItemDef>,
 }
 
+// New recipes only need an entry here
+const RECIPES: &[Recipe] = &[
+	Recipe {
+		inputs: &[Item::Heal, Item::Heal],
+		output: Item::GreaterHeal,
+		name: "greater healing potion",
+		char: '!',
+		color: colors::LIGHT_VIOLET,
+	},
+	Recipe {
+		inputs: &[Item::Lightning, Item::Lightning],
+		output: Item::Fireball,
+		name: "scroll of fireball",
+		char: '#',
+		color: colors::LIGHT_YELLOW,
+	},
+	Recipe {
+		inputs: &[Item::FireFlask, Item::Food],
+		output: Item::Heal,
+		name: "healing potion",
+		char: '!',
+		color: colors::VIOLET,
+	},
+];
+
+// The recipes a player can actually make: food only turns up with the hunger clock on
+fn recipes() -> Vec<&'static Recipe> {
+	RECIPES.iter().filter(|recipe| HUNGER_ENABLED || !recipe.inputs.contains(&Item::Food)).collect()
+}
 
-fn make_map(objects: &mut Vec<Object>) -> Map {
-	// fill map with wall tiles
-	let mut map = vec![vec![Tile::wall(); MAP_HEIGHT as usize]; MAP_WIDTH as usize];
-	
-	let mut rooms = vec![];
-
-	for _ in 0..MAX_ROOMS {
-		//random width and height
-		let w = rand::thread_rng().gen_range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
-		let h = rand::thread_rng().gen_range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
-
-		//random position without going out of the map boundaries
-		let x = rand::thread_rng().gen_range(0, MAP_WIDTH - w);
-		let y = rand::thread_rng().gen_range(0, MAP_HEIGHT - h);
-
-		let new_room = Rect::new(x, y, w, h);
-
-		// run through the other rooms and see if they intersect with this one
-		let failed = rooms.iter().any(|other_room| new_room.intersects_with(other_room));
-
-		if !failed {
-				// No intersections, so room is valid
-
-				create_room(new_room, &mut map);
-
-				// Add content to the room
-				place_objects(new_room, &map, objects);
-
-				// center coordinates of the new room, useful later
-				let (new_x, new_y) = new_room.center();
-
-				if rooms.is_empty() {
-					// this is the first room where the player starts
-					objects[PLAYER].set_pos(new_x, new_y);
-				} else {
-					// all rooms after the first:
-					// Connect it to the previous room with a runnel
-
-					// center coordinates of the previous room
-					let (prev_x, prev_y) = rooms[rooms.len() -1].center();
-
-					// flip a coin
-					if rand::random() {
-						//first move horizontally, then vertically
-						create_h_tunnel(prev_x, new_x, prev_y, &mut map);
-						create_v_tunnel(prev_y, new_y, new_x, &mut map);
-					} else {
-						// first move vertically, then horizontally
-						create_v_tunnel(prev_y, new_y, prev_x, &mut map);
-						create_h_tunnel(prev_x, new_x, new_y, &mut map);
-					}
-				
-				}
+/////////////////////
+/////
+/////  Functions
+/////
+/////////////////////
 
-			// finally append the new room to the list
-			rooms.push(new_room);
+// Pick one of the options, each with a chance proportional to its weight
+fn pick_weighted<'a, T, F: Fn(&T) -> f32>(options: &'a [T], weight: F, rng: &mut StdRng) -> &'a T {
+	let total: f32 = options.iter().map(&weight).sum();
+	let mut dice = rng.gen::<f32>() * total;
+	for option in options {
+		dice -= weight(option);
+		if dice < 0.0 {
+			return option;
 		}
 	}
-
-
-	map
+	// only reached through rounding errors
+	&options[options.len() - 1]
 }
 
-fn create_room(room: Rect, map: &mut Map) {
-	for x in (room.x1 + 1)..room.x2 {
-		for y in (room.y1 + 1)..room.y2 {
-			map[x as usize][y as usize] = Tile::empty();
-		}
-	}
+fn monster_in_view(objects: &mut [Object], game: &Game, tcod: &Tcod) -> bool {
+	// any monster the player can currently see is a threat
+	closest_monster(TORCH_RADIUS, objects, game, tcod).is_some()
 }
 
-fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map){
-	for x in cmp::min(x1, x2)..(cmp::max(x1, x2) + 1) {
-		map[x as usize][y as usize] = Tile::empty();
+fn continue_rest(tcod: &Tcod, objects: &mut [Object], game: &mut Game) -> PlayerAction {
+	// stop resting as soon as a monster shows up or the player dies
+	if !objects[PLAYER].alive {
+		game.rest_turns = 0;
+		return PlayerAction::DidntTakeTurn;
+	}
+	if monster_in_view(objects, game, tcod) {
+		game.rest_turns = 0;
+		game.log.add("You stop resting, an enemy is in sight!", colors::RED);
+		return PlayerAction::DidntTakeTurn;
 	}
-}
 
-fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map){
-	for y in cmp::min(y1, y2)..(cmp::max(y1, y2) + 1) {
-		map[x as usize][y as usize] = Tile::empty();
+	game.rest_turns -= 1;
+	if game.rest_turns == 0 {
+		game.log.add("You finish resting.", colors::WHITE);
 	}
+	PlayerAction::TookTurn
 }
 
-fn closest_monster(max_range: i32, objects: &mut [Object], tcod: &Tcod) -> Option<usize> {
-	let mut closest_enemy = None;
-	let mut closest_dist = (max_range + 1) as f32;
-
-	for (id, object) in objects.iter().enumerate() {
-		if (id != PLAYER) && object.fighter.is_some() && object.ai.is_some() &&
-			tcod.fov.is_in_fov(object.x, object.y)
-		{
-			// calculate distance between this object and the player
-			let dist = objects[PLAYER].distance_to(object);
-			if dist < closest_dist {
-				closest_enemy = Some(id);
-				closest_dist = dist;
+// Carry out a command and remember it, so it can be repeated
+fn run_command(command: Command, tcod: &mut Tcod, objects: &mut [Object], game: &mut Game) -> PlayerAction {
+	game.last_command = Some(command);
+	match command {
+		Command::Move(dx, dy) => {
+			// when nearly dead, walking towards a monster has to be asked for twice in a row
+			if LOW_HP_CONFIRM && is_low_hp(&objects[PLAYER], game) && steps_towards_danger(dx, dy, objects, game, tcod)
+					&& game.low_hp_warned != Some(command) {
+				game.low_hp_warned = Some(command);
+				game.log.add("Your health is low! Move that way again to go anyway.", colors::RED);
+				return PlayerAction::DidntTakeTurn;
+			}
+			game.low_hp_warned = None;
+			player_move_or_attack(dx, dy, objects, game, &mut tcod.fov);
+			PlayerAction::TookTurn
+		}
+		Command::Rest => {
+			// rest for a while, but only if no enemies are around
+			if monster_in_view(objects, game, tcod) {
+				game.log.add("You can't rest while enemies are near.", colors::RED);
+				PlayerAction::DidntTakeTurn
+			} else {
+				game.log.add("You sit down to rest.", colors::WHITE);
+				game.rest_turns = REST_TURNS - 1;
+				PlayerAction::TookTurn
 			}
-
 		}
+		// let a turn go by without doing anything
+		Command::Wait => PlayerAction::TookTurn,
+	}
+}
 
+// The way a key moves the look cursor, if it's one of the arrows or the numpad
+fn look_direction(key: Key) -> Option<(i32, i32)> {
+	use tcod::input::KeyCode::*;
+	match key.code {
+		Up | NumPad8 => Some((0, -1)),
+		Down | NumPad2 => Some((0, 1)),
+		Left | NumPad4 => Some((-1, 0)),
+		Right | NumPad6 => Some((1, 0)),
+		NumPad7 => Some((-1, -1)),
+		NumPad9 => Some((1, -1)),
+		NumPad1 => Some((-1, 1)),
+		NumPad3 => Some((1, 1)),
+		_ => None,
 	}
+}
 
-	closest_enemy
+fn is_low_hp(object: &Object, game: &Game) -> bool {
+	object.alive && object.fighter.is_some_and(|f| (f.hp as f32) < object.max_hp(game) as f32 * LOW_HP_FRACTION)
+}
+
+// Moving (not attacking) by (dx, dy) would bring the player closer to a monster they can see
+fn steps_towards_danger(dx: i32, dy: i32, objects: &[Object], game: &Game, tcod: &Tcod) -> bool {
+	let (x, y) = objects[PLAYER].pos();
+	if attackable_at(x + dx, y + dy, objects).is_some() {
+		return false;
+	}
+	hostiles_in_fov(objects, game, &tcod.fov)
+		.into_iter()
+		.any(|id| objects[id].distance(x + dx, y + dy) < objects[id].distance(x, y))
 }
 
 fn handle_keys(key: Key, tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut Game) -> PlayerAction {
@@ -606,6 +882,22 @@ fn handle_keys(key: Key, tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut
 	use tcod::input::KeyCode::*;
 
 	
+	// while looking with the keyboard, the arrows and numpad move the cursor instead of the player
+	// and say what's under it. The vi keys are left alone, as 'n' starts a new game once dead
+	if let Some((x, y)) = tcod.look_cursor {
+		if key.code == Escape {
+			tcod.look_cursor = None;
+			return DidntTakeTurn;
+		}
+		if let Some((dx, dy)) = look_direction(key) {
+			let (x, y) = ((x + dx).clamp(0, MAP_WIDTH - 1), (y + dy).clamp(0, MAP_HEIGHT - 1));
+			tcod.look_cursor = Some((x, y));
+			let text = describe_at(x, y, objects, game, &tcod.fov);
+			game.log.add(text, colors::LIGHT_GREY);
+			return DidntTakeTurn;
+		}
+	}
+
 	let player_alive = objects[PLAYER].alive;
 	match (key, player_alive) {
 
@@ -616,57 +908,252 @@ fn handle_keys(key: Key, tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut
 			DidntTakeTurn
 		}
 
-		// Exit game
-		(Key { code: Escape, .. }, _) => return Exit,
+		// Exit game, but only once the player says so; the run is saved on the way out
+		(Key { code: Escape, .. }, _) => {
+			if confirm("Really quit?", QUIT_MENU_WIDTH, tcod) {
+				Exit
+			} else {
+				DidntTakeTurn
+			}
+		}
+
+		// Hold Tab to see more of the message log
+		(Key { code: Tab, .. }, _) => {
+			peek_messages(tcod, game);
+			DidntTakeTurn
+		}
+
+		// Show the player's stats
+		(Key { printable: 'c', .. }, _) => {
+			let player = &objects[PLAYER];
+			let hp = player.fighter.map_or(0, |f| f.hp);
+			let text = format!("Character information\n\nDungeon level: {}\nTurns taken: {}\nSeed: {}\nNew Game+: {}\n\n\
+								HP: {}/{}\nAttack: {}\nDefense: {}",
+							   game.dungeon_level, game.stats.turns, game.seed, game.ng_plus, hp, player.max_hp(game), player.power(game),
+							   player.defense(game));
+			msgbox(&text, CHARACTER_SCREEN_WIDTH, tcod);
+			DidntTakeTurn
+		}
+
+		// Once dead, start over
+		(Key { printable: 'n', .. }, false) => NewGame,
+
+		// Show or hide the minimap
+		(Key { printable: 'm', .. }, _) => {
+			tcod.minimap = !tcod.minimap;
+			DidntTakeTurn
+		}
+
+		// Open or close a door next to the player
+		(Key { printable: 'o', .. }, true) => toggle_adjacent_door(objects, game, &mut tcod.fov),
+
+		// Scroll back through everything in the message log
+		(Key { printable: 'p', .. }, _) => {
+			show_messages(tcod, game);
+			DidntTakeTurn
+		}
+
+		// Look back over the run so far, dead or alive
+		(Key { printable: 'j', .. }, _) => {
+			show_journal(tcod, game);
+			DidntTakeTurn
+		}
+
+		// Switch between looking with the mouse and with a keyboard cursor
+		(Key { printable: 'v', .. }, _) | (Key { printable: 'x', .. }, _) => {
+			tcod.look_cursor = match tcod.look_cursor {
+				Some(_) => None,
+				None => {
+					game.log.add("Look around with the arrow keys or numpad, Escape to stop.", colors::LIGHT_GREY);
+					Some(objects[PLAYER].pos())
+				}
+			};
+			DidntTakeTurn
+		}
+
+		// Hidden: time the AI on a throwaway level, then drawing this one
+		(Key { code: F12, .. }, _) => {
+			run_benchmark(&game.content);
+			run_render_benchmark(tcod, objects, game);
+			DidntTakeTurn
+		}
+
+		// Debug: throw this level away and make a new one, to look at lots of layouts quickly
+		(Key { code: F11, .. }, _) if game.debug => {
+			regenerate_level(tcod, objects, game);
+			DidntTakeTurn
+		}
+
+		// Debug: show where monsters are heading and where the player's sight ends
+		(Key { code: F10, .. }, _) if game.debug => {
+			game.debug_overlay = !game.debug_overlay;
+			DidntTakeTurn
+		}
 
 
 		// Movement Keys
-		(Key { code: Up, .. }, true) => {
-			player_move_or_attack(0, -1, &game.map, objects, &mut game.log);
-			TookTurn
-		},
-		(Key { code: Down, .. }, true) => {
-			player_move_or_attack(0, 1, &game.map, objects, &mut game.log);
-			TookTurn
-		},
-		(Key { code: Left, .. }, true) => {
-			player_move_or_attack(-1, 0, &game.map, objects, &mut game.log);
-			TookTurn
-		},
-		(Key { code: Right, .. }, true) => {
-			player_move_or_attack(1, 0, &game.map, objects, &mut game.log);
-			TookTurn
-		},
+		(Key { code: Up, .. }, true) => run_command(Command::Move(0, -1), tcod, objects, game),
+		(Key { code: Down, .. }, true) => run_command(Command::Move(0, 1), tcod, objects, game),
+		(Key { code: Left, .. }, true) => run_command(Command::Move(-1, 0), tcod, objects, game),
+		(Key { code: Right, .. }, true) => run_command(Command::Move(1, 0), tcod, objects, game),
+
+		// the numpad, and the vi keys for diagonals
+		(Key { code: NumPad8, .. }, true) => run_command(Command::Move(0, -1), tcod, objects, game),
+		(Key { code: NumPad2, .. }, true) => run_command(Command::Move(0, 1), tcod, objects, game),
+		(Key { code: NumPad4, .. }, true) => run_command(Command::Move(-1, 0), tcod, objects, game),
+		(Key { code: NumPad6, .. }, true) => run_command(Command::Move(1, 0), tcod, objects, game),
+		(Key { code: NumPad7, .. }, true) | (Key { printable: 'y', .. }, true) =>
+			run_command(Command::Move(-1, -1), tcod, objects, game),
+		(Key { code: NumPad9, .. }, true) | (Key { printable: 'u', .. }, true) =>
+			run_command(Command::Move(1, -1), tcod, objects, game),
+		(Key { code: NumPad1, .. }, true) | (Key { printable: 'b', .. }, true) =>
+			run_command(Command::Move(-1, 1), tcod, objects, game),
+		(Key { code: NumPad3, .. }, true) | (Key { printable: 'n', .. }, true) =>
+			run_command(Command::Move(1, 1), tcod, objects, game),
+		(Key { code: NumPad5, .. }, true) | (Key { printable: '.', .. }, true) =>
+			run_command(Command::Wait, tcod, objects, game),
+
+		// Repeat the last move or rest, unless a monster has come into view since
+		(Key { printable: '`', .. }, true) => {
+			if !hostiles_in_fov(objects, game, &tcod.fov).is_empty() {
+				game.last_command = None;
+			}
+			match game.last_command {
+				Some(command) => run_command(command, tcod, objects, game),
+				None => DidntTakeTurn,
+			}
+		}
 
 		(Key { printable: 'g', .. }, true) => {
 			// pick up an item
-			let item_id = objects.iter().position(|object| {
-				object.pos() == objects[PLAYER].pos() && object.item.is_some()
-			});
-			if let Some(item_id) = item_id {
-				pick_item_up(item_id, objects, &mut game.inventory, &mut game.log);
+			let (player_x, player_y) = objects[PLAYER].pos();
+			if let Some(item_id) = item_at(player_x, player_y, objects) {
+				pick_item_up(item_id, objects, game);
+			}
+			DidntTakeTurn
+		}
+
+		(Key { printable: '>', .. }, true) => {
+			// go down the stairs, if the player is on or right next to them
+			if stairs_in_reach(objects, game) {
+				next_level(tcod, objects, game);
+			} else {
+				game.log.add("There are no stairs here.", colors::WHITE);
 			}
 			DidntTakeTurn
 		}
 
+		(Key { printable: 'F', .. }, true) => {
+			// panic: run for the stairs, and down them once they're in reach
+			if stairs_in_reach(objects, game) {
+				next_level(tcod, objects, game);
+				DidntTakeTurn
+			} else if flee_to_stairs(objects, game) {
+				TookTurn
+			} else {
+				game.log.add("You don't know where any stairs are!", colors::RED);
+				DidntTakeTurn
+			}
+		}
+
+		(Key { printable: 'l', .. }, true) => {
+			// loot the remains the player is standing on
+			let (player_x, player_y) = objects[PLAYER].pos();
+			// taking something costs a turn, the same as looting by walking into the remains
+			let looted = match corpse_at(player_x, player_y, objects) {
+				Some(corpse_id) => loot_corpse(corpse_id, objects, game),
+				None => {
+					game.log.add("There is nothing here to loot.", colors::WHITE);
+					false
+				}
+			};
+			if looted { TookTurn } else { DidntTakeTurn }
+		}
+
 		(Key { printable: 'i', .. }, true) => {
 			// show the inventory: if an item is selected, use it
 			let inventory_index = inventory_menu(
 				&game.inventory, "Press the key next to an item to use it or any other to cancel\n",
-				&mut tcod.root);
-			if let Some(inventory_index) = inventory_index {
-				use_item(inventory_index, objects, game, tcod);
+				tcod);
+			match inventory_index {
+				Some(inventory_index) if use_item(inventory_index, objects, game, tcod).took_turn() => TookTurn,
+				_ => DidntTakeTurn,
+			}
+		}
+
+		(Key { printable: 'r', .. }, true) => run_command(Command::Rest, tcod, objects, game),
+
+		(Key { printable: 'a', .. }, true) => {
+			// give all allies the next command in turn
+			let mut new_command = None;
+			for object in objects.iter_mut() {
+				if let Some(command) = object.ai.as_mut().and_then(Ai::ally_command_mut) {
+					let next = *new_command.get_or_insert(command.next());
+					*command = next;
+				}
+			}
+			match new_command {
+				Some(command) => game.log.add(format!("Your allies are now {}.", command.description()),
+								colors::LIGHT_BLUE),
+				None => game.log.add("You have no allies to command.", colors::WHITE),
 			}
 			DidntTakeTurn
 		}
 
+		(Key { printable: 'z', .. }, true) => {
+			// show the known spells; if one is selected, cast it
+			let options: Vec<_> = game.spells.iter()
+				.map(|spell| format!("{} ({} mana)", spell.name(), spell.cost()))
+				.collect();
+			if options.is_empty() {
+				game.log.add("You don't know any spells.", colors::WHITE);
+			} else if let Some(spell_id) = menu("Press the key next to a spell to cast it, or any other to cancel.\n",
+					&options, INVENTORY_WIDTH, tcod) {
+				cast_spell(spell_id, objects, game, tcod);
+			}
+			DidntTakeTurn
+		}
+
+		(Key { printable: 'k', .. }, true) => {
+			// show the recipes, marking the ones the player can't make yet
+			let recipes = recipes();
+			let options = recipes.iter().map(|recipe| {
+				if recipe_ingredients(recipe, &game.inventory).is_some() {
+					recipe.name.to_string()
+				} else {
+					format!("{} (missing ingredients)", recipe.name)
+				}
+			}).collect::<Vec<_>>();
+			let choice = menu("Press the key next to a recipe to craft it, or any other to cancel.\n",
+							&options, INVENTORY_WIDTH, tcod);
+			match choice {
+				Some(recipe_id) if craft_item(recipes[recipe_id], &mut game.inventory, &mut game.log) => {
+					add_journal_entry(game, format!("Crafted a {}.", recipes[recipe_id].name));
+					TookTurn
+				}
+				_ => DidntTakeTurn,
+			}
+		}
+
+		(Key { printable: 'w', .. }, true) => {
+			// put the worn loadout away and the other one on
+			swap_loadout(&mut objects[PLAYER], game);
+			DidntTakeTurn
+		}
+
+		(Key { printable: 'T', .. }, true) => {
+			// take off all equipment, to fight bare-handed or to start a new set from scratch
+			unequip_all(&mut objects[PLAYER], game);
+			DidntTakeTurn
+		}
+
 		(Key { printable: 'd', .. }, true) => {
 			// show the inventory; if an item is selected, drop it
 			let inventory_index = inventory_menu(
 				&game.inventory,
-				"Press the key next to an item to drop it, or any other to cancel.\n", &mut tcod.root);
+				"Press the key next to an item to drop it, or any other to cancel.\n", tcod);
 			if let Some(inventory_index) = inventory_index {
-				drop_item(inventory_index, &mut game.inventory, objects, &mut game.log);
+				drop_item(inventory_index, objects, game);
 			}
 			DidntTakeTurn
 		}
@@ -675,191 +1162,265 @@ fn handle_keys(key: Key, tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut
 	}
 }
 
-////////////////////
-/////////////
-/////////
-/////    SPELLS
-/////////
-/////////////
-///////////////////
-
+// Burn every creature standing in fire, then let the flames die down a little
+fn update_fire(objects: &mut [Object], game: &mut Game) {
+	for obj in objects.iter_mut() {
+		if obj.fighter.is_some() && !obj.is_immune(Effect::Fire) && game.map.get(obj.x, obj.y).burning > 0 {
+			game.log.add(format!("The {} is burned by the flames for {} hit points.", obj.name, FIRE_DAMAGE),
+				colors::ORANGE);
+			obj.take_damage(FIRE_DAMAGE, &mut game.log, &mut game.stats);
+		}
+	}
 
-fn cast_heal(_inventory_id: usize, objects: &mut [Object], game: &mut Game, _tcod: &mut Tcod)
-             -> UseResult
-{
-    // heal the player
-    if let Some(fighter) = objects[PLAYER].fighter {
-        if fighter.hp == fighter.max_hp {
-            message(&mut game.log, "You are already at full health.", colors::RED);
-            return UseResult::Cancelled;
-        }
-        message(&mut game.log, "Your wounds start to feel better!", colors::LIGHT_VIOLET);
-        objects[PLAYER].heal(HEAL_AMOUNT);
-        return UseResult::UsedUp;
-    }
-    UseResult::Cancelled
-}
-
-fn cast_lightning(_inventory_id: usize, objects: &mut [Object], game: &mut Game, tcod: &mut Tcod)
-                  -> UseResult
-{
-    // find closest enemy (inside a maximum range and damage it)
-    let monster_id = closest_monster(LIGHTNING_RANGE, objects, tcod);
-    if let Some(monster_id) = monster_id {
-        // zap it!
-        message(&mut game.log,
-                format!("A lightning bolt strikes the {} with a loud thunder! \
-                         The damage is {} hit points.",
-                        objects[monster_id].name, LIGHTNING_DAMAGE),
-                colors::LIGHT_BLUE);
-        objects[monster_id].take_damage(LIGHTNING_DAMAGE, &mut game.log);
-        UseResult::UsedUp
-    } else {  // no enemy found within maximum range
-        message(&mut game.log, "No enemy is close enough to strike.", colors::RED);
-        UseResult::Cancelled
-    }
+	for tile in game.map.tiles_mut() {
+		if tile.burning > 0 {
+			tile.burning -= 1;
+		}
+	}
 }
 
-fn cast_confuse(_inventory_id: usize, objects: &mut [Object], game: &mut Game, tcod: &mut Tcod)
-                -> UseResult
-{
-    // ask the player for a target to confuse
-    message(&mut game.log, "Left-click an enemy to confuse it, or right-click to cancel.",
-            colors::LIGHT_CYAN);
-    let monster_id = target_monster(tcod, objects, game, Some(CONFUSE_RANGE as f32));
-    if let Some(monster_id) = monster_id {
-        let old_ai = objects[monster_id].ai.take().unwrap_or(Ai::Basic);
-        // replace the monster's AI with a "confused" one; after
-        // some turns it will restore the old AI
-        objects[monster_id].ai = Some(Ai::Confused {
-            previous_ai: Box::new(old_ai),
-            num_turns: CONFUSE_NUM_TURNS,
-        });
-        message(&mut game.log,
-                format!("The eyes of {} look vacant, as he starts to stumble around!",
-                        objects[monster_id].name),
-                colors::LIGHT_GREEN);
-        UseResult::UsedUp
-    } else {  // no enemy fonud within maximum range
-        message(&mut game.log, "No enemy is close enough to strike.", colors::RED);
-        UseResult::Cancelled
-    }
+// Spikes hurt every creature that ends its turn on them
+fn update_spikes(objects: &mut [Object], game: &mut Game) {
+	for obj in objects.iter_mut() {
+		if obj.alive && obj.fighter.is_some() && game.map.get(obj.x, obj.y).spikes {
+			game.log.add(format!("The {} is hurt by the spikes for {} hit points.", obj.name, SPIKE_DAMAGE),
+				colors::LIGHT_RED);
+			obj.take_damage(SPIKE_DAMAGE, &mut game.log, &mut game.stats);
+		}
+	}
 }
 
-fn cast_fireball(_inventory_id: usize, objects: &mut [Object], game: &mut Game, tcod: &mut Tcod)
-                 -> UseResult
-{
-    // ask the player for a target tile to throw a fireball at
-    message(&mut game.log,
-            "Left-click a target tile for the fireball, or right-click to cancel.",
-            colors::LIGHT_CYAN);
-    let (x, y) = match target_tile(tcod, objects, game, None) {
-        Some(tile_pos) => tile_pos,
-        None => return UseResult::Cancelled,
-    };
-    message(&mut game.log,
-            format!("The fireball explodes, burning everything within {} tiles!", FIREBALL_RADIUS),
-            colors::ORANGE);
-
-    for obj in objects {
-        if obj.distance(x, y) <= FIREBALL_RADIUS as f32 && obj.fighter.is_some() {
-            message(&mut game.log,
-                    format!("The {} gets burned for {} hit points.", obj.name, FIREBALL_DAMAGE),
-                    colors::ORANGE);
-            obj.take_damage(FIREBALL_DAMAGE, &mut game.log);
-
-        }
-    }
+// A monster walking onto a net gets tangled up in it, and the net is used up
+fn update_nets(objects: &mut [Object], game: &mut Game) {
+	for obj in objects.iter_mut() {
+		if obj.is_hostile() && obj.alive && game.map.get(obj.x, obj.y).net {
+			game.map.get_mut(obj.x, obj.y).net = false;
+			obj.held_turns = NET_HOLD_TURNS;
+			game.log.add(format!("The {} is caught in the net!", obj.name), colors::LIGHT_CYAN);
+		}
+	}
+}
 
-    UseResult::UsedUp
+// Detected monsters only stay visible for a while
+fn update_detection(game: &mut Game) {
+	if game.detect_turns > 0 {
+		game.detect_turns -= 1;
+		if game.detect_turns == 0 {
+			game.log.add("You can no longer sense the monsters.", colors::LIGHT_CYAN);
+		}
+	}
+	if game.see_invisible_turns > 0 {
+		game.see_invisible_turns -= 1;
+		if game.see_invisible_turns == 0 {
+			game.log.add("The shimmering in the air fades.", colors::LIGHT_CYAN);
+		}
+	}
 }
 
+// An invisible object on a visible tile only shows up right next to the player,
+// or while the player can see invisible things
+fn can_see(object: &Object, objects: &[Object], game: &Game, fov_map: &FovMap) -> bool {
+	fov_map.is_in_fov(object.x, object.y)
+		&& (!object.invisible || game.see_invisible_turns > 0 || object.distance_to(&objects[PLAYER]) < 2.0)
+}
 
-fn use_item(inventory_id: usize, objects: &mut [Object], game: &mut Game, tcod: &mut Tcod) {
-    use Item::*;
-    // just call the "use_function" if it is defined
-    if let Some(item) = game.inventory[inventory_id].item {
-        let on_use: fn(usize, &mut [Object], &mut Game, &mut Tcod) -> UseResult = match item {
-            Heal => cast_heal,
-            Lightning => cast_lightning,
-            Confuse => cast_confuse,
-            Fireball => cast_fireball,
-        };
-        match on_use(inventory_id, objects, game, tcod) {
-            UseResult::UsedUp => {
-                // destroy after use, unless it was cancelled for some reason
-                game.inventory.remove(inventory_id);
-            }
-            UseResult::Cancelled => {
-                message(&mut game.log, "Cancelled", colors::WHITE);
-            }
-        }
-    } else {
-        message(&mut game.log,
-                format!("The {} cannot be used.", game.inventory[inventory_id].name),
-                colors::WHITE);
-    }
+// Catch your breath a little every turn
+fn update_stamina(game: &mut Game) {
+	if STAMINA_ENABLED && game.stamina < MAX_STAMINA {
+		game.stamina += 1;
+	}
 }
 
-fn drop_item(inventory_id: usize, inventory: &mut Vec<Object>, objects: &mut Vec<Object>, messages: &mut Messages) {
-	let mut item = inventory.remove(inventory_id);
-	item.set_pos(objects[PLAYER].x, objects[PLAYER].y);
-	message(messages, format!("You dropped a {}", item.name), colors::YELLOW);
-	objects.push(item);
+// Mana slowly comes back over time
+fn update_mana(game: &mut Game) {
+	if game.stats.turns.is_multiple_of(MANA_REGEN_INTERVAL) && game.mana < game.max_mana {
+		game.mana += 1;
+	}
 }
 
-fn get_names_under_mouse(mouse: Mouse, objects: &[Object], fov_map: &FovMap) -> String {
-	let (x, y) = (mouse.cx as i32, mouse.cy as i32);
+// Keep track of how close to death the player has come on this level
+fn update_hp_low(objects: &[Object], game: &mut Game) {
+	if let Some(fighter) = objects[PLAYER].fighter {
+		let fraction = fighter.hp as f32 / objects[PLAYER].max_hp(game) as f32;
+		game.hp_low = game.hp_low.min(fraction);
+	}
+}
 
-	// create a list with the names of all objects at the mouse's coordinates and in FOV
-	let names = objects
-		.iter()
-		.filter(|obj| {obj.pos() == (x, y) && fov_map.is_in_fov(obj.x, obj.y)})
-		.map(|obj| obj.name.clone())
-		.collect::<Vec<_>>();
+// Nudge the difficulty by how the level that just ended went, and start tracking the next one
+fn adjust_difficulty(game: &mut Game) {
+	if DYNAMIC_DIFFICULTY {
+		let turns = game.stats.turns - game.level_start_turn;
+		if game.hp_low < STRUGGLING_HP_FRACTION {
+			game.difficulty -= DIFFICULTY_STEP;
+		} else if game.hp_low > CRUISING_HP_FRACTION && turns < QUICK_LEVEL_TURNS {
+			game.difficulty += DIFFICULTY_STEP;
+		}
+		game.difficulty = game.difficulty.clamp(MIN_DIFFICULTY, MAX_DIFFICULTY);
+	}
+	game.hp_low = 1.0;
+	game.level_start_turn = game.stats.turns;
+}
 
-	names.join(", ") // Join the names, separated by commas
+// Wounds slowly close over time; heal already stops at max HP
+fn update_regen(objects: &mut [Object], game: &Game) {
+	if REGEN_ENABLED && objects[PLAYER].alive && game.stats.turns.is_multiple_of(REGEN_INTERVAL) {
+		objects[PLAYER].heal(1, game);
+	}
 }
 
-// Move by the given amount if destination isn't blocked
-fn move_by(id: usize, dx: i32, dy: i32, map: &Map, objects: &mut [Object]){
-	let (x, y) = objects[id].pos();
-	if !is_blocked(x + dx, y + dy, map, objects) {
-		objects[id].set_pos(x + dx, y + dy);
+// The player gets a little hungrier every turn, and starves once the food runs out
+fn update_hunger(objects: &mut [Object], game: &mut Game) {
+	if !HUNGER_ENABLED {
+		return;
+	}
+
+	if game.satiation > 0 {
+		game.satiation -= 1;
+		if game.satiation == HUNGRY_SATIATION {
+			game.log.add("You are getting hungry.", colors::YELLOW);
+		}
+	} else {
+		game.log.add("You are starving!", colors::RED);
+		objects[PLAYER].take_damage(STARVATION_DAMAGE, &mut game.log, &mut game.stats);
 	}
 }
 
-fn player_move_or_attack(dx: i32, dy: i32, map: &Map, objects: &mut [Object], messages: &mut Messages) {
+fn player_move_or_attack(dx: i32, dy: i32, objects: &mut [Object], game: &mut Game, fov: &mut FovMap) {
 	// the coordinates the player is moving to/attacking
 	let x = objects[PLAYER].x + dx;
 	let y = objects[PLAYER].y + dy;
 
-	// Look for an attackable object there
-	let target_id = objects.iter().position(|object| {
-		object.fighter.is_some() && object.pos() == (x, y)
-	});
+	// do the first thing in BUMP_ORDER that makes sense for that tile
+	for &bump in BUMP_ORDER {
+		if try_bump(bump, x, y, objects, game, fov) {
+			return;
+		}
+	}
+}
+
+// Bump the tile at (x, y) in one way, returning false if that doesn't apply there
+fn try_bump(bump: Bump, x: i32, y: i32, objects: &mut [Object], game: &mut Game, fov: &mut FovMap) -> bool {
+	match bump {
+		Bump::Attack => match attackable_at(x, y, objects) {
+			Some(target_id) => {
+				let (player, target) = mut_two(PLAYER, target_id, objects);
+				let mut power = player.power(game);
+				if STAMINA_ENABLED {
+					// too tired to swing properly: only half as hard
+					if game.stamina < ATTACK_STAMINA {
+						game.log.add("You are exhausted!", colors::LIGHT_GREY);
+						power /= 2;
+					}
+					game.stamina = cmp::max(0, game.stamina - ATTACK_STAMINA);
+				}
+				player.attack_with_power(power, target, game);
+				true
+			}
+			None => false,
+		},
+		Bump::Swap => match fighter_at(x, y, objects) {
+			Some(ally_id) if objects[ally_id].is_ally() => {
+				// never attack an ally, trade places with it instead
+				let (player_x, player_y) = objects[PLAYER].pos();
+				objects[ally_id].set_pos(player_x, player_y);
+				objects[PLAYER].set_pos(x, y);
+				game.log.add(format!("You swap places with the {}.", objects[ally_id].name), colors::WHITE);
+				true
+			}
+			_ => false,
+		},
+		Bump::Loot => match corpse_at(x, y, objects) {
+			// only when there is something to take and room to take it
+			Some(corpse_id) if !objects[corpse_id].loot.is_empty() && game.inventory.len() < game.inventory_capacity => {
+				loot_corpse(corpse_id, objects, game)
+			}
+			_ => false,
+		},
+		Bump::Door => {
+			let tile = game.map.get(x, y);
+			if !tile.is_door || tile.open {
+				return false;
+			}
+			set_door(x, y, true, objects, &mut game.map, fov);
+			game.log.add("You open the door.", colors::WHITE);
+			true
+		}
+		Bump::Move => {
+			let (player_x, player_y) = objects[PLAYER].pos();
+			move_by(PLAYER, x - player_x, y - player_y, &game.map, objects);
+			objects[PLAYER].pos() == (x, y)
+		}
+	}
+}
+
+// Open or close the door at (x, y), and relight the player's view so whatever is behind it
+// shows up straight away instead of on the next step
+fn set_door(x: i32, y: i32, open: bool, objects: &[Object], map: &mut Map, fov: &mut FovMap) {
+	let tile = map.get_mut(x, y);
+	tile.open = open;
+	tile.blocked = !open;
+	tile.block_sight = !open;
+	fov.set(x, y, open, open);
+	let (player_x, player_y) = objects[PLAYER].pos();
+	fov.compute_fov(player_x, player_y, TORCH_RADIUS, FOV_LIGHT_WALLS, FOV_ALGO);
+}
+
+// Open a closed door next to the player, or failing that close an open one nothing is standing in
+fn toggle_adjacent_door(objects: &[Object], game: &mut Game, fov: &mut FovMap) -> PlayerAction {
+	let (player_x, player_y) = objects[PLAYER].pos();
+	let mut doors = vec![];
+	for dy in -1..=1 {
+		for dx in -1..=1 {
+			let (x, y) = (player_x + dx, player_y + dy);
+			if game.map.in_bounds(x, y) && game.map.get(x, y).is_door {
+				doors.push((x, y));
+			}
+		}
+	}
 
-	// Attack if target found, otherwise move
-	match target_id {
-		Some(target_id) => {
-			let (player, target) = mut_two(PLAYER, target_id, objects);
-			player.attack(target, messages);
+	if let Some(&(x, y)) = doors.iter().find(|&&(x, y)| !game.map.get(x, y).open) {
+		set_door(x, y, true, objects, &mut game.map, fov);
+		game.log.add("You open the door.", colors::WHITE);
+		return PlayerAction::TookTurn;
+	}
+	let closable = doors.iter().find(|&&(x, y)| !objects.iter().any(|o| o.blocks && o.pos() == (x, y)));
+	match closable {
+		Some(&(x, y)) => {
+			set_door(x, y, false, objects, &mut game.map, fov);
+			game.log.add("You close the door.", colors::WHITE);
+			PlayerAction::TookTurn
+		}
+		None if !doors.is_empty() => {
+			game.log.add("Something is in the way of the door.", colors::LIGHT_GREY);
+			PlayerAction::DidntTakeTurn
 		}
 		None => {
-			move_by(PLAYER, dx, dy, map, objects);
+			game.log.add("There is no door here.", colors::LIGHT_GREY);
+			PlayerAction::DidntTakeTurn
 		}
 	}
 }
 
-// return the position of a tile left-clickde in player's FOV or (none, none) if right-clicked
+// The map tile under the mouse, or None while it's over the panel. Everything that looks at
+// the mouse goes through here, so a scrolling camera only has to change this
+fn screen_to_world(mouse: &Mouse, map: &Map) -> Option<(i32, i32)> {
+	let (x, y) = (mouse.cx as i32, mouse.cy as i32);
+	if map.in_bounds(x, y) {
+		Some((x, y))
+	} else {
+		None
+	}
+}
+
+// return the position of a tile left-clicked in player's FOV or (none, none) if right-clicked
 fn target_tile(tcod: &mut Tcod, objects: &[Object], game: &mut Game,
-			max_range: Option<f32>) -> Option<(i32, i32)> {
+			max_range: Option<f32>, radius: Option<f32>) -> Option<(i32, i32)> {
 
 	use tcod::input::KeyCode::Escape;
 	loop {
 		// render the screen. this erases the inventory and shows the names of objects under the mouse
 		tcod.root.flush();
-		let event = input::check_for_event(input::KEY_PRESS | input::MOUSE).map(|e| e.1);
+		let event = tcod.input.check_for_event(input::KEY_PRESS | input::MOUSE);
 		let mut key = None;
 		match event {
 			Some(Event::Mouse(m)) => tcod.mouse = m,
@@ -868,18 +1429,24 @@ fn target_tile(tcod: &mut Tcod, objects: &[Object], game: &mut Game,
 		}
 		render_all(tcod, objects, game, false);
 
-		let (x, y) = (tcod.mouse.cx as i32, tcod.mouse.cy as i32);
+		// mark where a click would land. render_all redraws the whole map every time round,
+		// so the highlight follows the mouse and never leaves old ones behind
+		if let Some(aim) = screen_to_world(&tcod.mouse, &game.map) {
+			let aim = within_range(&objects[PLAYER], aim, max_range);
+			highlight_target(tcod, objects, game, aim, radius);
+		}
 
-		// accept the target if the player clicked in FOV, and in case a rang
-		// is specified, if it's in that range
-		let in_fov = (x < MAP_WIDTH) && (y < MAP_HEIGHT) && tcod.fov.is_in_fov(x, y);
-		let in_range = max_range.map_or(
-			true, |range| objects[PLAYER].distance(x, y) <= range);
-		if tcod.mouse.lbutton_pressed && in_fov && in_range {
-			return Some((x, y))
+		// accept the target if the player clicked on the map; clicks over the panel are ignored
+		let clicked = screen_to_world(&tcod.mouse, &game.map).filter(|_| tcod.mouse.lbutton_pressed);
+		if let Some(aim) = clicked {
+			let (x, y) = within_range(&objects[PLAYER], aim, max_range);
+			if tcod.fov.is_in_fov(x, y) {
+				debug_assert!(game.map.in_bounds(x, y));
+				return Some((x, y))
+			}
 		}
 
-		let escape = key.map_or(false, |k| k.code == Escape);
+		let escape = key.is_some_and(|k| k.code == Escape);
 		if tcod.mouse.rbutton_pressed || escape {
 			return None // cancel if the player right clicked or pressed Esc
 		}
@@ -888,188 +1455,482 @@ fn target_tile(tcod: &mut Tcod, objects: &[Object], game: &mut Game,
 	}
 }
 
-fn render_all(tcod: &mut Tcod, objects: &[Object], game: &mut Game,
-		 fov_recompute: bool){
-	if fov_recompute {
-		// recompute FOV if needed
-		let player = &objects[0];
-		tcod.fov.compute_fov(player.x, player.y, TORCH_RADIUS, FOV_LIGHT_WALLS, FOV_ALGO);
+// If a range is given and the tile is beyond it, the furthest tile towards it that is still in range
+fn within_range(from: &Object, (x, y): (i32, i32), max_range: Option<f32>) -> (i32, i32) {
+	match max_range {
+		Some(range) if from.distance(x, y) > range => {
+			tcod::line::Line::new(from.pos(), (x, y))
+				.take_while(|&(line_x, line_y)| from.distance(line_x, line_y) <= range)
+				.last()
+				.unwrap_or(from.pos())
+		}
+		_ => (x, y),
 	}
+}
 
-	//go through all the tiles and set their background color
-	for y in 0..MAP_HEIGHT {
-		for x in 0..MAP_WIDTH {
-			let visible = tcod.fov.is_in_fov(x, y);
-			let wall = game.map[x as usize][y as usize].block_sight;
-			let color = match (visible, wall) {
-				// outside of field of view:
-				(false, true) => COLOR_DARK_WALL,
-				(false, false) => COLOR_DARK_GROUND,
-				// inside fov:
-				(true, true) => COLOR_LIGHT_WALL,
-				(true, false) => COLOR_LIGHT_GROUND,
-			};
-
-			let explored = &mut game.map[x as usize][y as usize].explored;
-			if visible {
-				// since it's visible, explore it
-				*explored = true;
+// Light up the aimed-at tile on the root console, and with a radius the area around it,
+// with the visible monsters caught in it flashing
+fn highlight_target(tcod: &mut Tcod, objects: &[Object], game: &Game, (x, y): (i32, i32), radius: Option<f32>) {
+	if let Some(radius) = radius {
+		let reach = radius.ceil() as i32;
+		for area_y in (y - reach)..=(y + reach) {
+			for area_x in (x - reach)..=(x + reach) {
+				let in_blast = ((area_x - x).pow(2) + (area_y - y).pow(2)) as f32 <= radius * radius;
+				if in_blast && game.map.in_bounds(area_x, area_y) && tcod.fov.is_in_fov(area_x, area_y) {
+					tcod.root.set_char_background(area_x, area_y, COLOR_TARGET_AREA, BackgroundFlag::Set);
+				}
 			}
-			if *explored {
-				// show explored tiles only
-				tcod.con.set_char_background(x, y, color, BackgroundFlag::Set);
+		}
+
+		let flash_on = tcod::system::get_elapsed_time().as_millis() % (2 * TARGET_FLASH_MILLIS) < TARGET_FLASH_MILLIS;
+		if flash_on {
+			for object in objects.iter().filter(|o| o.fighter.is_some() && o.distance(x, y) <= radius) {
+				if can_see(object, objects, game, &tcod.fov) {
+					tcod.root.set_char_background(object.x, object.y, COLOR_TARGET, BackgroundFlag::Set);
+				}
 			}
 		}
 	}
 
-	let mut to_draw: Vec<_> = objects.iter().filter(|o| tcod.fov.is_in_fov(o.x, o.y)).collect();
-	
-	// sort so that non-blocking objects come first
-	to_draw.sort_by(|o1, o2| { o1.blocks.cmp(&o2.blocks) });
-	
-	// draw the objects in the list
-	for object in &to_draw {
-		object.draw(&mut tcod.con);
+	// out of sight tiles can't be picked, so they stay dim
+	let color = if tcod.fov.is_in_fov(x, y) { COLOR_TARGET } else { colors::DARK_GREY };
+	tcod.root.set_char_background(x, y, color, BackgroundFlag::Set);
+}
+
+fn initialize_fov(map: &Map, fov: &mut FovMap) {
+	for y in 0..MAP_HEIGHT {
+		for x in 0..MAP_WIDTH {
+			fov.set(x, y, !map.get(x, y).block_sight, !map.get(x, y).blocked);
+		}
 	}
+}
 
+// The nearest stairs the player has seen, if they've seen any
+fn known_stairs(objects: &[Object], game: &Game) -> Option<usize> {
+	let stairs = (0..objects.len())
+		.filter(|&id| objects[id].name == "stairs" && game.map.get(objects[id].x, objects[id].y).explored);
+	nearest_to(PLAYER, stairs, objects)
+}
 
-	// blit the contents of "con" to the root console
-	blit(&tcod.con, (0, 0), (MAP_WIDTH, MAP_HEIGHT), &mut tcod.root, (0, 0), 1.0, 1.0);
+fn stairs_in_reach(objects: &[Object], game: &Game) -> bool {
+	known_stairs(objects, game).is_some_and(|id| objects[PLAYER].distance_to(&objects[id]) <= QUICK_DESCEND_RANGE)
+}
 
+// Take a step along the way to the nearest known stairs, telling whether there are any
+fn flee_to_stairs(objects: &mut [Object], game: &mut Game) -> bool {
+	let stairs_id = match known_stairs(objects, game) {
+		Some(stairs_id) => stairs_id,
+		None => return false,
+	};
+	refresh_pathing(game);
+	move_astar(PLAYER, stairs_id, &game.map, &mut game.pathing, objects);
+	true
+}
 
-	// Prepare to render the GUI panel
-	tcod.panel.set_default_background(colors::BLACK);
-	tcod.panel.clear();
+// Go down the stairs to a new, deeper level, resting on the way
+fn next_level(tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut Game) {
+	game.log.add("You take a moment to rest, and recover your strength.", colors::VIOLET);
+	let max_hp = objects[PLAYER].max_hp(game);
+	objects[PLAYER].heal(max_hp, game);
+	adjust_difficulty(game);
+
+	game.dungeon_level += 1;
+	game.stats.deepest_level = cmp::max(game.stats.deepest_level, game.dungeon_level);
+	game.log.add("After a rare moment of peace, you descend deeper into the heart of the dungeon...",
+				colors::RED);
+	add_journal_entry(game, format!("Went down to dungeon level {}.", game.dungeon_level));
+	rebuild_level(tcod, objects, game);
+}
 
-	// Print the game messages, one line at a time
-	let mut y = MSG_HEIGHT as i32;
-	for &(ref msg, color) in game.log.iter().rev() {
-		let msg_height = tcod.panel.get_height_rect(MSG_X, y, MSG_WIDTH, 0, msg);
-		y -= msg_height;
-		if y < 0 {
-			break;
-		}
+// Make a fresh level in place of the current one, keeping only the player and their inventory
+fn regenerate_level(tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut Game) {
+	// the same seed would only make the same level again
+	game.seed = tcod.input.new_seed();
+	rebuild_level(tcod, objects, game);
+	game.log.add(format!("The level shifts around you (seed {}).", game.seed), colors::LIGHT_GREY);
+	add_journal_entry(game, "The level shifted around you.");
+}
 
-		tcod.panel.set_default_foreground(color);
-		tcod.panel.print_rect(MSG_X, y, MSG_WIDTH, 0, msg);
+fn rebuild_level(tcod: &mut Tcod, objects: &mut Vec<Object>, game: &mut Game) {
+	objects.truncate(PLAYER + 1);
+	let difficulty = game.difficulty * ng_plus_difficulty(game.ng_plus);
+	game.map = make_map(objects, &game.content, &game.map_settings, game.dungeon_level, game.seed, difficulty);
+	initialize_fov(&game.map, &mut tcod.fov);
+	let (player_x, player_y) = objects[PLAYER].pos();
+	tcod.fov.compute_fov(player_x, player_y, TORCH_RADIUS, FOV_LIGHT_WALLS, FOV_ALGO);
+	if game.practice_spawn.is_some() {
+		game.practice_spawn = Some((player_x, player_y));
 	}
+}
 
-	// show the player's stats
-	let hp = objects[PLAYER].fighter.map_or(0,|f| f.hp);
-	let max_hp = objects[PLAYER].fighter.map_or(0, |f| f.max_hp);
-	render_bar(&mut tcod.panel, 1, 1, BAR_WIDTH, "HP", hp, max_hp, colors::LIGHT_RED, colors::DARKER_RED);
+// Check what the player can see and has done, and teach them about it
+fn update_tutorial(objects: &[Object], game: &mut Game, fov_map: &FovMap) {
+	let wounded = objects[PLAYER].fighter.is_some_and(|f| f.hp < objects[PLAYER].max_hp(game) / 2);
+	let monster_seen = !hostiles_in_fov(objects, game, fov_map).is_empty();
+	let tutorial = match game.tutorial {
+		Some(ref mut tutorial) => tutorial,
+		None => return,
+	};
+	let visible = |object: &&Object| fov_map.is_in_fov(object.x, object.y);
 
-	// display names of objects under the mouse
-	tcod.panel.set_default_foreground(colors::LIGHT_GREY);
-	tcod.panel.print_ex(1, 0, BackgroundFlag::None, TextAlignment::Left,
-					get_names_under_mouse(tcod.mouse, objects, &tcod.fov));
+	if monster_seen {
+		tutorial.hint(TutorialHint::MonsterSeen, &mut game.log);
+	}
+	if objects.iter().filter(visible).any(|object| object.item.is_some()) {
+		tutorial.hint(TutorialHint::ItemSeen, &mut game.log);
+	}
+	if objects.iter().skip(1).filter(visible).any(|object| object.is_corpse()) {
+		tutorial.hint(TutorialHint::CorpseSeen, &mut game.log);
+	}
+	if !game.inventory.is_empty() {
+		tutorial.hint(TutorialHint::ItemPickedUp, &mut game.log);
+	}
+	if wounded {
+		tutorial.hint(TutorialHint::Wounded, &mut game.log);
+	}
+}
 
-	// blit the contents of 'panel' to the root console
-	blit(&tcod.panel, (0, 0), (SCREEN_WIDTH, PANEL_HEIGHT), &mut tcod.root, (0, PANEL_Y), 1.0, 1.0);
+// Runs many AI turns on a separate level, with no rendering, and prints how long they took
+fn run_benchmark(content: &Content) {
+	let mut player = Object::new(0, 0, '@', "player", colors::WHITE, true);
+	player.alive = true;
+	player.fighter = Some(Fighter{max_hp: 1_000_000, hp: 1_000_000, defense: 0, power: 0,
+				on_death: DeathCallBack::Player, range: 0.0});
+	let mut objects = vec![player];
+	let seed = rand::random();
+	let mut game = Game {
+		map: make_map(&mut objects, content, &NORMAL_DUNGEON, 1, seed, 1.0),
+		log: MessageLog::new(MSG_LOG_CAPACITY),
+		inventory: vec![],
+		inventory_capacity: INVENTORY_CAPACITY,
+		rest_turns: 0,
+		satiation: MAX_SATIATION,
+		stats: GameStats { deepest_level: 1, ..Default::default() },
+		mana: MAX_MANA,
+		stamina: MAX_STAMINA,
+		max_mana: MAX_MANA,
+		spells: vec![],
+		difficulty: 1.0,
+		hp_low: 1.0,
+		level_start_turn: 0,
+		loadouts: Default::default(),
+		loadout: 0,
+		ng_plus: 0,
+		tutorial: None,
+		practice_spawn: None,
+		detect_turns: 0,
+		see_invisible_turns: 0,
+		last_command: None,
+		low_hp_warned: None,
+		debug_overlay: false,
+		map_settings: NORMAL_DUNGEON,
+		debug: false,
+		content: content.clone(),
+		journal: vec![],
+		dungeon_level: 1,
+		seed,
+		rng: combat_rng(seed),
+		pathing: FovMap::new(MAP_WIDTH, MAP_HEIGHT),
+	};
+
+	// fill the level with orcs on random free tiles
+	while objects.iter().filter(|object| object.ai.is_some()).count() < BENCH_MONSTERS {
+		let x = rand::thread_rng().gen_range(1, MAP_WIDTH - 1);
+		let y = rand::thread_rng().gen_range(1, MAP_HEIGHT - 1);
+		if !is_blocked(x, y, &game.map, &objects) {
+			let mut orc = Object::new(x, y, 'o', "orc", colors::DESATURATED_GREEN, true);
+			orc.fighter = Some(Fighter{max_hp: 10, hp: 10, defense: 0, power: 3, on_death: DeathCallBack::Monster,
+				range: 0.0});
+			orc.ai = Some(Ai::Basic);
+			orc.alive = true;
+			objects.push(orc);
+		}
+	}
+
+	let start = Instant::now();
+	let mut monster_turns = 0;
+	for _ in 0..BENCH_TURNS {
+		refresh_pathing(&mut game);
+		for id in 0..objects.len() {
+			if objects[id].ai.is_some() {
+				ai_take_turn(id, &mut game, &mut objects);
+				monster_turns += 1;
+			}
+		}
+	}
+	let seconds = start.elapsed().as_secs_f64();
+	println!("Benchmark: {} monster turns ({} monsters, {} rounds) in {:.3}s, {:.0} turns per second",
+		monster_turns, BENCH_MONSTERS, BENCH_TURNS, seconds, monster_turns as f64 / seconds);
 }
 
-fn render_bar(panel: &mut Offscreen,
-				x: i32,
-				y: i32,
-				total_width: i32,
-				name: &str,
-				value: i32,
-				maximum: i32,
-				bar_color: Color,
-				back_color: Color)
-{
-	// render a bar (hp, exp, etc) First calculate width of bar
-	let bar_width = (value as f32 / maximum as f32 * total_width as f32) as i32;
+// Draws the current level over and over, FOV included, and prints how long the frames took
+fn run_render_benchmark(tcod: &mut Tcod, objects: &[Object], game: &mut Game) {
+	let start = Instant::now();
+	for _ in 0..BENCH_FRAMES {
+		render_all(tcod, objects, game, true);
+	}
+	let seconds = start.elapsed().as_secs_f64();
+	println!("Benchmark: {} frames in {:.3}s, {:.0} frames per second",
+		BENCH_FRAMES, seconds, BENCH_FRAMES as f64 / seconds);
+}
 
-	// render the background first
-	panel.set_default_background(back_color);
-	panel.rect(x, y, total_width, 1, false, BackgroundFlag::Screen);
+fn is_font_file(path: &Path) -> bool {
+	// libtcod can load PNG and BMP fonts, so check for either signature
+	let mut header = [0; 8];
+	match File::open(path).and_then(|mut file| file.read_exact(&mut header)) {
+		Ok(()) => header == *b"\x89PNG\r\n\x1a\n" || header[..2] == *b"BM",
+		Err(_) => false,
+	}
+}
 
-	// now render the bar on top
-	panel.set_default_background(bar_color);
-	if bar_width > 0 {
-		panel.rect(x, y, bar_width, 1, false, BackgroundFlag::Screen);
+// Where to look for the font and data files: the working directory first, then next to the executable
+fn search_dirs() -> Vec<PathBuf> {
+	let mut dirs = vec![PathBuf::from(".")];
+	if let Some(exe_dir) = env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf)) {
+		dirs.push(exe_dir);
 	}
+	dirs
+}
 
-	// Centered text with the values
-	panel.set_default_foreground(colors::WHITE);
-	panel.print_ex(x + total_width / 2, y, BackgroundFlag::None, TextAlignment::Center,
-				&format!("{}: {}/{}", name, value, maximum));
+// The character code of a glyph's sprite in the tileset, or the glyph itself if it has none
+fn sprite_glyph(glyph: char) -> char {
+	TILESET_SPRITES.iter()
+		.position(|&sprite| sprite == glyph)
+		.and_then(|index| std::char::from_u32(SPRITE_CODE + index as u32))
+		.unwrap_or(glyph)
 }
 
-fn message<T: Into<String>>(messages: &mut Messages, message: T, color: Color) {
-	// if the buffer is full, remove the first message to make room for the new one
-	if messages.len() == MSG_HEIGHT {
-		messages.remove(0);
+fn check_tileset(path: &Path) -> Result<(), String> {
+	// the PNG header holds the image size, so the grid can be checked without decoding it
+	let mut header = [0; 24];
+	File::open(path)
+		.and_then(|mut file| file.read_exact(&mut header))
+		.map_err(|error| format!("Could not read the tileset {}: {}", path.display(), error))?;
+	if header[..8] != *b"\x89PNG\r\n\x1a\n" {
+		return Err(format!("The tileset {} is not a PNG image.", path.display()));
+	}
+	let width = u32::from_be_bytes([header[16], header[17], header[18], header[19]]);
+	let height = u32::from_be_bytes([header[20], header[21], header[22], header[23]]);
+	if width % TILESET_COLUMNS as u32 != 0 || height % TILESET_ROWS as u32 != 0 {
+		return Err(format!("The tileset {} is {}x{} pixels, which doesn't divide into {} columns and {} rows of tiles.",
+			path.display(), width, height, TILESET_COLUMNS, TILESET_ROWS));
 	}
-	// add the new line as a tuple with the text and the color
-	messages.push((message.into(), color));
+	Ok(())
+}
 
+fn find_font() -> Option<(PathBuf, FontLayout, FontType)> {
+	let dirs = search_dirs();
+	for &(name, layout, font_type) in FONTS {
+		for dir in &dirs {
+			let path = dir.join(name);
+			if is_font_file(&path) {
+				return Some((path, layout, font_type));
+			}
+		}
+	}
+	None
 }
 
-fn menu<T: AsRef<str>>(header: &str, options: &[T], width: i32,
-						root: &mut Root) -> Option<usize> {
-	assert!(options.len() <= 26, "Cannot have a menu with more than 26 options.");
+// Read a data file, falling back on the copy built into the game if there isn't one
+fn load_data_file<T: DeserializeOwned>(name: &str, built_in: &str) -> Result<T, String> {
+	let path = search_dirs().into_iter().map(|dir| dir.join(name)).find(|path| path.is_file());
+	let text = match path {
+		Some(path) => fs::read_to_string(&path)
+			.map_err(|error| format!("Could not read {}: {}", path.display(), error))?,
+		None => built_in.to_string(),
+	};
+	toml::from_str(&text).map_err(|error| format!("{} is malformed: {}", name, error))
+}
 
-	// calculate total height for the header (after auto-wrap) and one line per option
-	let header_height = root.get_height_rect(0, 0, width, SCREEN_HEIGHT, header);
-	let height = options.len() as i32 + header_height;
+// Everything about a run in progress that survives quitting
+#[derive(Serialize, Deserialize)]
+struct SaveFile {
+	seed: u64,
+	dungeon_level: u32,
+	#[serde(default)]
+	stats: GameStats,
+	satiation: i32,
+	mana: i32,
+	stamina: i32,
+	max_mana: i32,
+	spells: Vec<Spell>,
+	detect_turns: i32,
+	see_invisible_turns: i32,
+	inventory_capacity: usize,
+	#[serde(default)]
+	practice_spawn: Option<(i32, i32)>,
+	#[serde(default = "default_difficulty")]
+	difficulty: f32,
+	#[serde(default = "default_difficulty")]
+	hp_low: f32,
+	#[serde(default)]
+	level_start_turn: u32,
+	#[serde(default)]
+	loadouts: [Vec<String>; 2],
+	#[serde(default)]
+	loadout: usize,
+	#[serde(default)]
+	ng_plus: u32,
+	messages: Vec<SavedMessage>,
+	journal: Vec<JournalEntry>,
+	objects: Vec<Object>,
+	inventory: Vec<Object>,
+	map: Map,
+}
 
-	// create off-screen console that represents the menu's window
-	let mut window = Offscreen::new(width, height);
+#[derive(Serialize, Deserialize)]
+struct SavedMessage {
+	text: String,
+	#[serde(with = "ColorDef")]
+	color: Color,
+}
 
-	// print the header, with auto-wrap
-	window.set_default_foreground(colors::WHITE);
-	window.print_rect_ex(0, 0, width, height, BackgroundFlag::None, TextAlignment::Left, header);
+impl SaveFile {
+	fn new(objects: &[Object], game: &Game) -> Self {
+		SaveFile {
+			seed: game.seed,
+			dungeon_level: game.dungeon_level,
+			stats: game.stats,
+			satiation: game.satiation,
+			mana: game.mana,
+			stamina: game.stamina,
+			max_mana: game.max_mana,
+			spells: game.spells.clone(),
+			detect_turns: game.detect_turns,
+			see_invisible_turns: game.see_invisible_turns,
+			inventory_capacity: game.inventory_capacity,
+			practice_spawn: game.practice_spawn,
+			difficulty: game.difficulty,
+			hp_low: game.hp_low,
+			level_start_turn: game.level_start_turn,
+			loadouts: game.loadouts.clone(),
+			loadout: game.loadout,
+			ng_plus: game.ng_plus,
+			messages: game.log.messages.iter()
+				.map(|&(ref text, color)| SavedMessage { text: text.clone(), color })
+				.collect(),
+			journal: game.journal.clone(),
+			objects: objects.to_vec(),
+			inventory: game.inventory.clone(),
+			map: game.map.clone(),
+		}
+	}
 
-	// print all the options
-	// enum method on iterator gets index for each loop through
-	// and uses that to display the corresponding option letter
-	for (index, option_text) in options.iter().enumerate() {
-		let menu_letter= (b'a' + index as u8) as char;
-		let text = format!("({}) {}", menu_letter, option_text.as_ref());
-		window.print_ex(0, header_height + index as i32,
-					BackgroundFlag::None, TextAlignment::Left, text);
+	fn restore(self, objects: &mut Vec<Object>, game: &mut Game) {
+		*objects = self.objects;
+		game.map = self.map;
+		game.inventory = self.inventory;
+		game.inventory_capacity = self.inventory_capacity;
+		game.seed = self.seed;
+		game.rng = combat_rng(self.seed);
+		game.dungeon_level = self.dungeon_level;
+		game.stats = self.stats;
+		game.stats.deepest_level = cmp::max(game.stats.deepest_level, self.dungeon_level);
+		game.satiation = self.satiation;
+		game.mana = self.mana;
+		game.stamina = self.stamina;
+		game.max_mana = self.max_mana;
+		game.spells = self.spells;
+		game.detect_turns = self.detect_turns;
+		game.see_invisible_turns = self.see_invisible_turns;
+		game.difficulty = self.difficulty;
+		game.hp_low = self.hp_low;
+		game.level_start_turn = self.level_start_turn;
+		game.loadouts = self.loadouts;
+		game.loadout = self.loadout;
+		game.ng_plus = self.ng_plus;
+		// whether it's a practice run is up to --practice this time, but the spot has to be on the
+		// saved level; a run saved without practice mode respawns where the player is now
+		if game.practice_spawn.is_some() {
+			game.practice_spawn = Some(self.practice_spawn.unwrap_or(objects[PLAYER].pos()));
+		}
+		game.journal = self.journal;
+		game.log = MessageLog::new(MSG_LOG_CAPACITY);
+		for message in self.messages {
+			game.log.messages.push((message.text, message.color));
+		}
+		game.log.start_turn();
 	}
+}
 
-	// blit the contents of "window" to the root console
-	let x = SCREEN_WIDTH / 2 - width / 2;
-	let y = SCREEN_HEIGHT / 2 - height / 2;
-	tcod::console::blit(&mut window, (0, 0), (width,height), root, (x, y), 1.0, 0.7);
+// JSON rather than TOML like the content files: TOML's integers stop at i64::MAX, and a seed can
+// be any u64
+fn save_game(objects: &[Object], game: &Game) -> Result<(), String> {
+	let text = serde_json::to_string(&SaveFile::new(objects, game))
+		.map_err(|error| format!("Could not save the game: {}", error))?;
+	fs::write(SAVE_FILE, text).map_err(|error| format!("Could not write {}: {}", SAVE_FILE, error))
+}
 
-	// present the root console to the player and wait for a keypress
-	root.flush();
-	let key = root.wait_for_keypress(true);
+// saves from before dynamic difficulty play as designed
+fn default_difficulty() -> f32 {
+	1.0
+}
 
-	// convert ASCII code to an index; if it corresponds to an option, return it
-	if key.printable.is_alphabetic() {
-		let index = key.printable.to_ascii_lowercase() as usize - 'a' as usize;
-		if index < options.len() {
-			Some(index)
-		} else {
-			None
-		}
-	} else {
-		None
+fn delete_save() -> Result<(), String> {
+	if Path::new(SAVE_FILE).exists() {
+		fs::remove_file(SAVE_FILE).map_err(|error| format!("Could not remove {}: {}", SAVE_FILE, error))?;
 	}
+	Ok(())
 }
 
-fn inventory_menu(inventory: &[Object], header: &str, root: &mut Root) -> Option<usize> {
-	// have a menu with each item of the inventory as an option
-	let options = if inventory.len() == 0 {
-		vec!["Inventory is empty".into()]
-	} else {
-		inventory.iter().map(|item| { item.name.clone() }).collect()
-	};
+fn load_game() -> Result<SaveFile, String> {
+	let text = fs::read_to_string(SAVE_FILE).map_err(|_| "There is no saved game to continue.".to_string())?;
+	serde_json::from_str(&text).map_err(|error| format!("The saved game in {} is damaged: {}", SAVE_FILE, error))
+}
+
+fn check_weight(file: &str, name: &str, weight: &Weight) -> Result<(), String> {
+	match weight {
+		Weight::Flat(weight) if *weight <= 0.0 => Err(format!("{}: the {} needs a positive weight.", file, name)),
+		Weight::Flat(_) => Ok(()),
+		Weight::ByLevel(table) => {
+			let in_order = table.windows(2).all(|pair| pair[0].level < pair[1].level);
+			if table.is_empty() || !in_order || table.iter().any(|transition| transition.value < 0.0) {
+				Err(format!("{}: the {} needs its weights listed by increasing level, none negative.", file, name))
+			} else {
+				Ok(())
+			}
+		}
+	}
+}
 
-	let inventory_index = menu(header, &options, INVENTORY_WIDTH, root);
+fn load_content() -> Result<Content, String> {
+	let monsters: MonsterFile = load_data_file("monsters.toml", include_str!("../monsters.toml"))?;
+	let items: ItemFile = load_data_file("items.toml", include_str!("../items.toml"))?;
 
-	// if an item was chose, return it
-	if inventory.len() > 0 {
-		inventory_index
-	} else {
-		None
+	// catch entries that would break spawning now, rather than halfway through a game
+	if monsters.monster.is_empty() {
+		return Err("monsters.toml doesn't define any monsters.".into());
 	}
+	if items.item.is_empty() {
+		return Err("items.toml doesn't define any items.".into());
+	}
+	for monster in &monsters.monster {
+		if monster.hp <= 0 {
+			return Err(format!("monsters.toml: the {} needs a positive hp.", monster.name));
+		}
+		check_weight("monsters.toml", &monster.name, &monster.weight)?;
+	}
+	for item in &items.item {
+		check_weight("items.toml", &item.name, &item.weight)?;
+	}
+	if !monsters.monster.iter().any(|monster| monster.name == DRAGON_NAME) {
+		return Err(format!("monsters.toml doesn't define the {}.", DRAGON_NAME));
+	}
+	// every level needs something that can spawn on it; past the last transition nothing changes
+	let deepest = monsters.monster.iter().map(|m| m.weight.last_change())
+		.chain(items.item.iter().map(|i| i.weight.last_change()))
+		.max()
+		.unwrap_or(1);
+	for level in 1..=deepest {
+		if monsters.monster.iter().all(|m| m.weight.at(level) <= 0.0) {
+			return Err(format!("monsters.toml: no monster can appear on level {}.", level));
+		}
+		if items.item.iter().all(|i| i.weight.at(level) <= 0.0) {
+			return Err(format!("items.toml: no item can appear on level {}.", level));
+		}
+	}
+
+	Ok(Content { monsters: monsters.monster, items: items.item })
 }
 
 ///            //|| ||\\      ///  //////   ///
@@ -1078,48 +1939,222 @@ fn inventory_menu(inventory: &[Object], header: &str, root: &mut Root) -> Option
 //  //// ///     || ||\\\\\   ///  ///   //////
 //   //////      || ||    \\  ///  ///    /////
 //    ////       || ||     \\ ///  ///     ////  
+// Kept apart from the level generator, so fighting doesn't change the levels still to come
+fn combat_rng(seed: u64) -> StdRng {
+	SeedableRng::from_seed(&[seed as usize][..])
+}
+
+// How much harder than designed the levels of a run are after winning it ng_plus times
+fn ng_plus_difficulty(ng_plus: u32) -> f32 {
+	1.0 + NG_PLUS_DIFFICULTY * ng_plus as f32
+}
+
+// A fresh run on the first level, with the player in the first room
+fn new_game(content: Content, map_settings: MapSettings, seed: u64, ng_plus: u32) -> (Vec<Object>, Game) {
+	// Place player inside first room
+	let mut player = Object::new(0, 0, '@', "player", colors::WHITE, true);
+	player.alive = true;
+	player.fighter = Some(Fighter{max_hp: 30, hp: 30, defense: 2, power: 5,
+		on_death: DeathCallBack::Player, range: 0.0});
+
+	// the list of objects with just the player
+	let mut objects = vec![player];
+
+	let mut game = Game {
+		// generate map
+		map: make_map(&mut objects, &content, &map_settings, 1, seed, ng_plus_difficulty(ng_plus)),
+		log: MessageLog::new(MSG_LOG_CAPACITY),
+		inventory: vec![],
+		inventory_capacity: INVENTORY_CAPACITY,
+		rest_turns: 0,
+		satiation: MAX_SATIATION,
+		stats: GameStats { deepest_level: 1, ..Default::default() },
+		mana: MAX_MANA,
+		stamina: MAX_STAMINA,
+		max_mana: MAX_MANA,
+		spells: vec![],
+		difficulty: 1.0,
+		hp_low: 1.0,
+		level_start_turn: 0,
+		loadouts: Default::default(),
+		loadout: 0,
+		ng_plus,
+		tutorial: if env::args().any(|arg| arg == "--tutorial") {
+			Some(Tutorial { shown: vec![] })
+		} else {
+			None
+		},
+		practice_spawn: None,
+		detect_turns: 0,
+		see_invisible_turns: 0,
+		last_command: None,
+		low_hp_warned: None,
+		debug_overlay: false,
+		map_settings,
+		debug: env::args().any(|arg| arg == "--debug"),
+		content,
+		journal: vec![],
+		dungeon_level: 1,
+		seed,
+		rng: combat_rng(seed),
+		pathing: FovMap::new(MAP_WIDTH, MAP_HEIGHT),
+	};
+
+	// in practice mode, dying sends the player back to where they started
+	if env::args().any(|arg| arg == "--practice") {
+		game.practice_spawn = Some(objects[PLAYER].pos());
+	}
+
+	// Welcome message
+	game.log.add("Welcome stranger! Prepare to slay the dragon", colors::RED);
+	add_journal_entry(&mut game, "Entered the dungeon.");
+	if let Some(ref mut tutorial) = game.tutorial {
+		tutorial.hint(TutorialHint::Start, &mut game.log);
+	}
+
+	(objects, game)
+}
+
+// After a victory, start over on a new, harder dungeon, keeping the player's strength and what
+// they're wearing. Everything else they carried stays behind
+fn new_game_plus(objects: &[Object], game: &Game, seed: u64) -> (Vec<Object>, Game) {
+	let ng_plus = game.ng_plus + 1;
+	let (mut fresh_objects, mut fresh_game) = new_game(game.content.clone(), game.map_settings, seed, ng_plus);
+	fresh_objects[PLAYER].fighter = objects[PLAYER].fighter;
+	fresh_game.inventory = game.inventory.iter()
+		.filter(|item| item.equipment.is_some_and(|e| e.equipped))
+		.cloned()
+		.collect();
+	let max_hp = fresh_objects[PLAYER].max_hp(&fresh_game);
+	if let Some(ref mut fighter) = fresh_objects[PLAYER].fighter {
+		fighter.hp = max_hp;
+	}
+	add_journal_entry(&mut fresh_game, format!("Began New Game+ {}.", ng_plus));
+	fresh_game.log.add(format!("New Game+ {}: the dungeon is crueler than before.", ng_plus), colors::RED);
+	(fresh_objects, fresh_game)
+}
+
+// The seed given with --seed=<number>, or a random one. Either way it's printed, so a run
+// can be shared or reported
+fn seed_from_args() -> Result<u64, String> {
+    let seed = match env::args().find(|arg| arg.starts_with("--seed=")) {
+        Some(arg) => arg["--seed=".len()..].parse()
+            .map_err(|_| format!("'{}' is not a seed, expected a whole number.", &arg["--seed=".len()..]))?,
+        None => rand::random(),
+    };
+    println!("Seed: {}", seed);
+    Ok(seed)
+}
+
 fn main() {
-    let root = Root::initializer()
-        .font("arial10x10.png", FontLayout::Tcod)
-        .font_type(FontType::Greyscale)
+    // run the game first, so the window and the rest of its state are
+    // dropped before the process exits
+    let code = match run() {
+        Ok(()) => {
+            println!("Farewell, dragonslayer.");
+            0
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            1
+        }
+    };
+    process::exit(code);
+}
+
+fn run() -> Result<(), String> {
+    // libtcod exits without explanation if the font can't be loaded,
+    // so find a usable one before opening the window
+    let (font, font_layout, font_type) = match find_font() {
+        Some(font) => font,
+        None => {
+            return Err(format!("Could not find a font to load. Dragonslayer needs {} in the directory \
+                                it is started from or next to the executable.", FONTS[0].0));
+        }
+    };
+    if !font.ends_with(FONTS[0].0) {
+        eprintln!("Could not load {}, falling back to {}.", FONTS[0].0, font.display());
+    }
+    let content = load_content()?;
+    let map_settings = MapSettings::from_args()?;
+    map_settings.validate()?;
+    let (input, seed) = input_from_args()?;
+
+    // like the font, make sure a tileset can be loaded before handing it to libtcod
+    let tileset = env::args()
+        .find(|arg| arg.starts_with("--tileset="))
+        .map(|arg| PathBuf::from(&arg["--tileset=".len()..]));
+    if let Some(ref path) = tileset {
+        check_tileset(path)?;
+    }
+
+    let mut initializer = Root::initializer();
+    match tileset {
+        Some(ref path) => initializer
+            .font(path, FontLayout::AsciiInRow)
+            .font_type(FontType::Default)
+            .font_dimensions(TILESET_COLUMNS, TILESET_ROWS),
+        None => initializer
+            .font(font, font_layout)
+            .font_type(font_type),
+    };
+    let mut root = initializer
         .size(SCREEN_WIDTH, SCREEN_HEIGHT)
         .title("Dragonslayer")
         .init();
+    if tileset.is_some() {
+        for (row, sprites) in TILESET_SPRITES.chunks(TILESET_COLUMNS as usize).enumerate() {
+            let row = row as i32;
+            root.map_ascii_codes_to_font(SPRITE_CODE as i32 + row * TILESET_COLUMNS, sprites.len() as i32,
+                                         0, TILESET_SPRITE_ROW + row);
+        }
+    }
     tcod::system::set_fps(FPS_LIMIT);
 
+    let layout = Layout::new(root.width(), root.height());
     let mut tcod = Tcod {
     	root: root,
     	con: Offscreen::new(MAP_WIDTH, MAP_HEIGHT),
-    	panel: Offscreen::new(SCREEN_WIDTH, PANEL_HEIGHT),
+    	panel: Offscreen::new(layout.panel_width, layout.panel_height),
     	fov: FovMap::new(MAP_WIDTH, MAP_HEIGHT),
     	mouse: Default::default(),
+    	layout,
+    	look_cursor: None,
+    	tileset: tileset.is_some(),
+    	minimap: false,
+    	input,
     };
 
 
-    // Place player inside first room
-    let mut player = Object::new(0, 0, '@', "player", colors::WHITE, true);
-    player.alive = true;
-    player.fighter = Some(Fighter{max_hp: 30, hp: 30, defense: 2, power: 5,
-    			 on_death: DeathCallBack::Player});
-
-    // the list of objects with just the player
-    let mut objects = vec![player];
+    let (mut objects, mut game) = new_game(content, map_settings, seed, 0);
 
-    let mut game = Game {
-    	// generate map
-    	map: make_map(&mut objects),
-    	log: vec![],
-    	inventory: vec![],
+    // pick up the last run where it was left, if the player wants to; --continue skips asking.
+    // Recordings always start a new run, since that's all a replay can start
+    let choice = if tcod.input.is_recording() || tcod.input.is_replay() {
+        MainMenuChoice::NewGame
+    } else if env::args().any(|arg| arg == "--continue") {
+        MainMenuChoice::Continue
+    } else {
+        main_menu(Path::new(SAVE_FILE).exists(), &mut tcod)
+    };
+    let continued = match choice {
+        MainMenuChoice::Quit => return Ok(()),
+        MainMenuChoice::NewGame => false,
+        MainMenuChoice::Continue => match load_game() {
+            Ok(save) => {
+                save.restore(&mut objects, &mut game);
+                true
+            }
+            Err(error) => {
+                eprintln!("{} Starting a new game instead.", error);
+                game.log.add(format!("{} Starting a new game instead.", error), colors::RED);
+                false
+            }
+        },
     };
 
     // create the FOV map
-    for y in 0..MAP_HEIGHT {
-        for x in 0..MAP_WIDTH {
-            tcod.fov.set(x, y,
-                         !game.map[x as usize][y as usize].block_sight,
-                         !game.map[x as usize][y as usize].blocked);
-        }
-    }
+    initialize_fov(&game.map, &mut tcod.fov);
 
     // create an NPC
     //let npc = Object::new(SCREEN_WIDTH / 2 - 5, SCREEN_HEIGHT / 2, '@', "npc", colors::YELLOW, true);
@@ -1134,22 +2169,23 @@ fn main() {
     // Keep track of keyboard states
     let mut key = Default::default();
 
-    // Welcome message
-    message(&mut game.log, "Welcome stranger! Prepare to slay the dragon", colors::RED);
+    if continued {
+        game.log.add("Welcome back, stranger! The dragon still awaits", colors::RED);
+    }
 
     ///////////////////////
     //					 //
     ////// Main Loop //////
     //				     //
  	///////////////////////
-    while !tcod.root.window_closed() {
+    while !tcod.root.window_closed() && tcod.input.replay_ended().is_none() {
 
     	// Clear the screen of the previous frame
     	tcod.con.clear();
 
-    	match input::check_for_event(input::MOUSE | input::KEY_PRESS) {
-    		Some((_, Event::Mouse(m))) => tcod.mouse = m,
-    		Some((_, Event::Key(k))) => key = k,
+    	match tcod.input.check_for_event(input::MOUSE | input::KEY_PRESS) {
+    		Some(Event::Mouse(m)) => tcod.mouse = m,
+    		Some(Event::Key(k)) => key = k,
     		_ => key = Default::default(),
     	}
 
@@ -1161,18 +2197,350 @@ fn main() {
 
     	// handle keys and exit game if needed
     	previous_player_position = objects[PLAYER].pos();
-    	let player_action = handle_keys(key, &mut tcod, &mut objects, &mut game);
+    	game.log.start_turn();
+    	let alive_before = objects[PLAYER].alive;
+    	let player_action = if game.rest_turns > 0 {
+    		continue_rest(&tcod, &mut objects, &mut game)
+    	} else {
+    		handle_keys(key, &mut tcod, &mut objects, &mut game)
+    	};
     	if player_action == PlayerAction::Exit {
     		break
     	}
+    	if player_action == PlayerAction::NewGame {
+    		let seed = tcod.input.new_seed();
+    		let (fresh_objects, fresh_game) = new_game(game.content.clone(), game.map_settings, seed, 0);
+    		objects = fresh_objects;
+    		game = fresh_game;
+    		initialize_fov(&game.map, &mut tcod.fov);
+    		tcod.look_cursor = None;
+    		previous_player_position = (-1, -1);
+    		continue;
+    	}
 
-    	// let monsters take their turn
-    	if objects[PLAYER].alive && player_action != PlayerAction::DidntTakeTurn {
+    	// let monsters take their turn, unless the player's action just ended the game
+    	if objects[PLAYER].alive && !game.stats.won && player_action != PlayerAction::DidntTakeTurn {
+    		game.stats.turns += 1;
+    		refresh_pathing(&mut game);
+    		for object in &mut objects {
+    			object.path.clear();
+    		}
     		for id in 0..objects.len() {
     			if objects[id].ai.is_some() {
-    				ai_take_turn(id, &mut game, &mut objects, &tcod.fov);
+    				ai_take_turn(id, &mut game, &mut objects);
+    			}
+    		}
+    		update_fire(&mut objects, &mut game);
+    		update_spikes(&mut objects, &mut game);
+    		update_nets(&mut objects, &mut game);
+    		update_hunger(&mut objects, &mut game);
+    		update_regen(&mut objects, &game);
+    		update_hp_low(&objects, &mut game);
+    		update_mana(&mut game);
+    		update_stamina(&mut game);
+    		update_detection(&mut game);
+    		split_monsters(&mut objects, &mut game);
+
+    		// remove whatever disappeared this turn
+    		objects.retain(|object| !object.vanished);
+    		tcod.input.end_turn(&objects, &game);
+    	}
+
+    	// a win counts even if the player went down in the same turn as the dragon
+    	if game.stats.won {
+    		add_journal_entry(&mut game, "Slew the dragon.");
+    		// the run is won, so it's over just as surely as if the player had died
+    		if !tcod.input.is_replay() {
+    			delete_save()?;
+    		}
+    		render_all(&mut tcod, &objects, &mut game, false);
+    		let text = run_summary("You slew the dragon!", &game);
+    		msgbox(&text, VICTORY_SCREEN_WIDTH, &mut tcod);
+    		let again = confirm("Start a New Game+, keeping your strength and what you wear?",
+    			VICTORY_SCREEN_WIDTH, &mut tcod);
+    		if !again || tcod.root.window_closed() || tcod.input.replay_ended().is_some() {
+    			break;
+    		}
+    		let seed = tcod.input.new_seed();
+    		let (fresh_objects, fresh_game) = new_game_plus(&objects, &game, seed);
+    		objects = fresh_objects;
+    		game = fresh_game;
+    		initialize_fov(&game.map, &mut tcod.fov);
+    		tcod.look_cursor = None;
+    		previous_player_position = (-1, -1);
+    		continue;
+    	}
+
+    	// whatever killed the player, their own spell included
+    	if alive_before && !objects[PLAYER].alive {
+    		add_journal_entry(&mut game, "Died.");
+    		if let Some((x, y)) = game.practice_spawn {
+    			respawn_player(x, y, &mut objects, &mut game);
+    		} else {
+    			// the run is over, so there's nothing left to continue
+    			if !tcod.input.is_replay() {
+    				delete_save()?;
+    			}
+    			render_all(&mut tcod, &objects, &mut game, false);
+    			let text = run_summary("You died!", &game);
+    			msgbox(&text, DEATH_SCREEN_WIDTH, &mut tcod);
+    			if GHOST_MODE {
+    				// let the player look around what killed them before leaving
+    				tcod.look_cursor = Some(objects[PLAYER].pos());
+    				game.log.add("You drift out of your body. Look around with the arrow keys, \
+    							  press N for a new game or Escape twice to leave.", colors::LIGHT_GREY);
+    			} else {
+    				game.log.add("Press N for a new game or Escape to leave.", colors::LIGHT_GREY);
     			}
     		}
     	}
+
+    	update_tutorial(&objects, &mut game, &tcod.fov);
     }
+
+    // a replay leaves the save alone, and says how it went: to the player, and as a failure if
+    // it diverged from the recording
+    if tcod.input.is_replay() {
+        let ended = tcod.input.replay_ended().cloned().unwrap_or(Ok(()));
+        tcod.input = Input::Live;
+        if !tcod.root.window_closed() {
+            render_all(&mut tcod, &objects, &mut game, false);
+            let text = match ended {
+                Ok(()) => "The replay is over.",
+                Err(ref error) => error,
+            };
+            msgbox(text, REPLAY_SCREEN_WIDTH, &mut tcod);
+        }
+        return ended;
+    }
+
+    // keep the run for next time, unless it's over
+    if objects[PLAYER].alive && !game.stats.won {
+        save_game(&objects, &game)?;
+    } else {
+        delete_save()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	// A fresh game on a wall-less map, with a hard to kill player at (10, 10) and nothing else
+	pub(crate) fn open_arena() -> (Vec<Object>, Game) {
+		let (mut objects, mut game) = new_game(load_content().unwrap(), NORMAL_DUNGEON, 1, 0);
+		objects.truncate(1);
+		game.map = Map::new(MAP_WIDTH, MAP_HEIGHT, Tile::floor(None));
+		objects[PLAYER].set_pos(10, 10);
+		if let Some(fighter) = objects[PLAYER].fighter.as_mut() {
+			fighter.max_hp = 1000;
+			fighter.hp = 1000;
+		}
+		(objects, game)
+	}
+
+	pub(crate) fn orc(x: i32, y: i32) -> Object {
+		let mut orc = Object::new(x, y, 'o', "orc", colors::DESATURATED_GREEN, true);
+		orc.fighter = Some(Fighter{max_hp: 10, hp: 10, defense: 0, power: 3, on_death: DeathCallBack::Monster,
+				range: 0.0});
+		orc.ai = Some(Ai::Basic);
+		orc.alive = true;
+		orc
+	}
+
+	// The FOV the player has from where they stand
+	pub(crate) fn player_fov(objects: &[Object], game: &Game) -> FovMap {
+		let mut fov = FovMap::new(MAP_WIDTH, MAP_HEIGHT);
+		initialize_fov(&game.map, &mut fov);
+		let (x, y) = objects[PLAYER].pos();
+		fov.compute_fov(x, y, TORCH_RADIUS, FOV_LIGHT_WALLS, FOV_ALGO);
+		fov
+	}
+
+	// Bump (11, 10) from the arena, returning whether it did anything
+	fn bump(bump: Bump, objects: &mut [Object], game: &mut Game) -> bool {
+		let mut fov = player_fov(objects, game);
+		try_bump(bump, 11, 10, objects, game, &mut fov)
+	}
+
+	#[test]
+	fn bump_attacks_a_monster() {
+		let (mut objects, mut game) = open_arena();
+		assert!(!bump(Bump::Attack, &mut objects, &mut game));
+		objects.push(orc(11, 10));
+		objects[1].fighter.as_mut().unwrap().defense = -100;
+		assert!(bump(Bump::Attack, &mut objects, &mut game));
+		assert!(!objects[1].alive);
+		assert_eq!(objects[PLAYER].pos(), (10, 10));
+	}
+
+	#[test]
+	fn bump_swaps_with_an_ally() {
+		let (mut objects, mut game) = open_arena();
+		objects.push(orc(11, 10));
+		assert!(!bump(Bump::Swap, &mut objects, &mut game));
+		objects[1].ai = Some(Ai::Ally{num_turns: 10, command: AllyCommand::Follow});
+		assert!(bump(Bump::Swap, &mut objects, &mut game));
+		assert_eq!(objects[PLAYER].pos(), (11, 10));
+		assert_eq!(objects[1].pos(), (10, 10));
+	}
+
+	#[test]
+	fn bump_loots_a_corpse() {
+		let (mut objects, mut game) = open_arena();
+		game.inventory.clear();
+		let mut corpse = Object::new(11, 10, '%', "remains of orc", colors::DARK_RED, false);
+		objects.push(corpse.clone());
+		assert!(!bump(Bump::Loot, &mut objects, &mut game));
+		let mut potion = Object::new(0, 0, '!', "healing potion", colors::VIOLET, false);
+		potion.item = Some(Item::Heal);
+		corpse.loot.push(potion);
+		objects[1] = corpse;
+		game.inventory_capacity = 0;
+		assert!(!bump(Bump::Loot, &mut objects, &mut game));
+		game.inventory_capacity = INVENTORY_CAPACITY;
+		assert!(bump(Bump::Loot, &mut objects, &mut game));
+		assert_eq!(game.inventory.len(), 1);
+		assert!(objects[1].loot.is_empty());
+	}
+
+	#[test]
+	fn bump_opens_a_door() {
+		let (mut objects, mut game) = open_arena();
+		assert!(!bump(Bump::Door, &mut objects, &mut game));
+		*game.map.get_mut(11, 10) = Tile::door();
+		assert!(bump(Bump::Door, &mut objects, &mut game));
+		assert!(game.map.get(11, 10).open);
+		assert!(!bump(Bump::Door, &mut objects, &mut game));
+		assert_eq!(objects[PLAYER].pos(), (10, 10));
+	}
+
+	#[test]
+	fn bump_moves_onto_free_tiles() {
+		let (mut objects, mut game) = open_arena();
+		*game.map.get_mut(11, 10) = Tile::wall();
+		assert!(!bump(Bump::Move, &mut objects, &mut game));
+		*game.map.get_mut(11, 10) = Tile::floor(None);
+		assert!(bump(Bump::Move, &mut objects, &mut game));
+		assert_eq!(objects[PLAYER].pos(), (11, 10));
+	}
+
+	#[test]
+	fn bump_order_attacks_before_moving() {
+		let (mut objects, mut game) = open_arena();
+		objects.push(orc(11, 10));
+		let mut fov = player_fov(&objects, &game);
+		let done = BUMP_ORDER.iter().find(|&&bump| try_bump(bump, 11, 10, &mut objects, &mut game, &mut fov));
+		assert_eq!(done, Some(&Bump::Attack));
+		assert_eq!(objects[PLAYER].pos(), (10, 10));
+	}
+
+	#[test]
+	fn fleeing_heads_for_known_stairs() {
+		let (mut objects, mut game) = open_arena();
+		let mut stairs = Object::new(20, 10, '>', "stairs", colors::WHITE, false);
+		stairs.always_visible = true;
+		objects.push(stairs);
+		assert!(!flee_to_stairs(&mut objects, &mut game), "they haven't been seen yet");
+		assert_eq!(objects[PLAYER].pos(), (10, 10));
+
+		game.map.get_mut(20, 10).explored = true;
+		for _ in 0..9 {
+			assert!(!stairs_in_reach(&objects, &game));
+			assert!(flee_to_stairs(&mut objects, &mut game));
+		}
+		assert_eq!(objects[PLAYER].pos(), (19, 10));
+		assert!(stairs_in_reach(&objects, &game));
+	}
+
+	#[test]
+	fn difficulty_follows_how_levels_go() {
+		if !DYNAMIC_DIFFICULTY {
+			return;
+		}
+		let (_, mut game) = open_arena();
+		game.hp_low = 0.1;
+		adjust_difficulty(&mut game);
+		assert!(game.difficulty < 1.0, "nearly died");
+		assert_eq!(game.hp_low, 1.0);
+
+		game.difficulty = 1.0;
+		game.stats.turns = game.level_start_turn + QUICK_LEVEL_TURNS / 2;
+		adjust_difficulty(&mut game);
+		assert!(game.difficulty > 1.0, "quick and unhurt");
+
+		game.difficulty = MAX_DIFFICULTY;
+		game.stats.turns += 1;
+		adjust_difficulty(&mut game);
+		assert_eq!(game.difficulty, MAX_DIFFICULTY);
+	}
+
+	#[test]
+	fn new_game_plus_keeps_strength_and_worn_gear() {
+		let (mut objects, mut game) = open_arena();
+		objects[PLAYER].fighter.as_mut().unwrap().hp = 0;
+		for &(name, worn) in &[("sword", true), ("shield", false)] {
+			let mut item = Object::new(0, 0, '/', name, colors::WHITE, false);
+			item.item = Some(Item::Sword);
+			item.equipment = equipment_for(Item::Sword);
+			item.equipment.as_mut().unwrap().equipped = worn;
+			game.inventory.push(item);
+		}
+		let mut potion = Object::new(0, 0, '!', "healing potion", colors::VIOLET, false);
+		potion.item = Some(Item::Heal);
+		game.inventory.push(potion);
+
+		let (fresh_objects, fresh) = new_game_plus(&objects, &game, 2);
+		assert_eq!(fresh.ng_plus, 1);
+		assert_eq!(fresh.dungeon_level, 1);
+		let names: Vec<_> = fresh.inventory.iter().map(|item| item.name.as_str()).collect();
+		assert_eq!(names, ["sword"]);
+		let fighter = fresh_objects[PLAYER].fighter.unwrap();
+		assert_eq!(fighter.max_hp, 1000);
+		assert_eq!(fighter.hp, fresh_objects[PLAYER].max_hp(&fresh));
+		assert!(ng_plus_difficulty(fresh.ng_plus) > ng_plus_difficulty(game.ng_plus));
+	}
+
+	#[test]
+	fn saves_keep_any_seed() {
+		let (objects, mut game) = open_arena();
+		game.seed = u64::MAX - 5;
+		let text = serde_json::to_string(&SaveFile::new(&objects, &game)).unwrap();
+		let (mut restored_objects, mut restored) = open_arena();
+		serde_json::from_str::<SaveFile>(&text).unwrap().restore(&mut restored_objects, &mut restored);
+		assert_eq!(restored.seed, u64::MAX - 5);
+		assert_eq!(restored_objects[PLAYER].pos(), objects[PLAYER].pos());
+	}
+
+	#[test]
+	fn practice_runs_respawn_on_the_saved_level() {
+		let (objects, mut game) = open_arena();
+		game.practice_spawn = Some((3, 4));
+		let save = SaveFile::new(&objects, &game);
+		let (mut restored_objects, mut restored) = open_arena();
+		restored.practice_spawn = Some((20, 20));
+		save.restore(&mut restored_objects, &mut restored);
+		assert_eq!(restored.practice_spawn, Some((3, 4)));
+
+		// saved without --practice, continued with it
+		game.practice_spawn = None;
+		let save = SaveFile::new(&objects, &game);
+		restored.practice_spawn = Some((20, 20));
+		save.restore(&mut restored_objects, &mut restored);
+		assert_eq!(restored.practice_spawn, Some(objects[PLAYER].pos()));
+	}
+
+	#[test]
+	fn every_content_glyph_has_a_sprite() {
+		let content = load_content().unwrap();
+		let glyphs = content.monsters.iter().map(|monster| (&monster.name, monster.glyph))
+			.chain(content.items.iter().map(|item| (&item.name, item.glyph)));
+		for (name, glyph) in glyphs {
+			assert!(TILESET_SPRITES.contains(&glyph), "no sprite for the {}'s '{}'", name, glyph);
+		}
+		let sprite_rows = TILESET_ROWS - TILESET_SPRITE_ROW;
+		assert!(TILESET_SPRITES.len() <= (sprite_rows * TILESET_COLUMNS) as usize);
+	}
 }
@@ -0,0 +1,573 @@
+// The dungeon itself: tiles, rooms, tunnels and making a level
+
+use super::*;
+use tcod::bsp::{Bsp, TraverseOrder};
+
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Rect {
+	pub(crate) x1: i32,
+	pub(crate) x2: i32,
+	pub(crate) y1: i32,
+	pub(crate) y2: i32,
+}
+
+impl Rect {
+	pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+		Rect{ x1: x, y1: y, x2: x + w, y2: y + h}
+	}
+
+	pub fn center(&self) -> (i32, i32) {
+		let center_x = (self.x1 + self.x2) / 2;
+		let center_y = (self.y1 + self.y2) / 2;
+		(center_x, center_y)
+	}
+
+	pub fn intersects_with(&self, other: &Rect) -> bool {
+		//returns true if this rectangle intersects with another one
+		(self.x1 <= other.x2) && (self.x2 >= other.x1) &&
+			(self.y1 <= other.y2) && (self.y2 >= other.y1)
+	}
+
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub(crate) struct Tile {
+	pub(crate) blocked: bool,
+	pub(crate) block_sight: bool,
+	pub(crate) explored: bool,
+	pub(crate) burning: i32,
+	pub(crate) spikes: bool,
+	pub(crate) net: bool, // set by the player, catches the next monster to walk in
+	#[serde(default)]
+	pub(crate) is_door: bool,
+	#[serde(default)]
+	pub(crate) open: bool, // only means something for doors; closed ones block like a wall
+	#[serde(default)]
+	pub(crate) kind: Option<TileType>, // what sort of floor this is, None for a plain room
+}
+
+// Floors that are drawn in their own colours, so the layout is easier to read
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum TileType {
+	Cave,
+	Corridor,
+	SpecialRoom,
+}
+
+impl Tile {
+	pub fn empty() -> Self {
+		Tile{ blocked: false, explored: false, block_sight: false, burning: 0, spikes: false, net: false,
+			  is_door: false, open: false, kind: None }
+	}
+
+	pub fn floor(kind: Option<TileType>) -> Self {
+		Tile{ kind, ..Tile::empty() }
+	}
+
+	pub fn wall() -> Self {
+		Tile{ blocked: true, explored: false, block_sight: true, burning: 0, spikes: false, net: false,
+			  is_door: false, open: false, kind: None }
+	}
+
+	pub fn door() -> Self {
+		Tile{ blocked: true, explored: false, block_sight: true, burning: 0, spikes: false, net: false,
+			  is_door: true, open: false, kind: None }
+	}
+}
+
+// The tiles of a level, stored row by row in a single vector
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Map {
+	pub(crate) tiles: Vec<Tile>,
+	pub(crate) width: i32,
+	pub(crate) height: i32,
+}
+
+impl Map {
+	pub fn new(width: i32, height: i32, tile: Tile) -> Self {
+		Map { tiles: vec![tile; (width * height) as usize], width, height }
+	}
+
+	pub(crate) fn index(&self, x: i32, y: i32) -> usize {
+		// checked in release too, or an x past the edge would quietly read the next row
+		assert!(self.in_bounds(x, y), "({}, {}) is outside the map", x, y);
+		(y * self.width + x) as usize
+	}
+
+	pub fn in_bounds(&self, x: i32, y: i32) -> bool {
+		x >= 0 && y >= 0 && x < self.width && y < self.height
+	}
+
+	pub fn get(&self, x: i32, y: i32) -> &Tile {
+		&self.tiles[self.index(x, y)]
+	}
+
+	pub fn get_mut(&mut self, x: i32, y: i32) -> &mut Tile {
+		let index = self.index(x, y);
+		&mut self.tiles[index]
+	}
+
+	pub fn tiles_mut(&mut self) -> std::slice::IterMut<'_, Tile> {
+		self.tiles.iter_mut()
+	}
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Corridor {
+	Straight,
+	Winding, // a random walk that drifts towards the other room
+	Wide, // two tiles across
+	Diagonal, // a staircase of short steps along the straight line
+}
+
+// How make_map lays out the rooms of a level
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum MapGenerator {
+	Rooms, // rooms dropped anywhere they fit, each tunnelled to the one before
+	Bsp, // the map cut up into halves and quarters, with a room in each piece
+}
+
+pub(crate) fn place_objects(room: Rect, map: &Map, objects: &mut Vec<Object>, content: &Content, level: u32,
+				 difficulty: f32, rng: &mut StdRng) {
+	// deeper levels are more crowded
+	let depth = level as i32 - 1;
+
+	// choose random number of monsters
+	let max_monsters = ((MAX_ROOM_MONSTERS + depth / LEVELS_PER_EXTRA_MONSTER) as f32 * difficulty).round() as i32;
+	let num_monsters = rng.gen_range(0, max_monsters + 1);
+
+	for _ in 0..num_monsters {
+		// choose random location for the monster
+		let x = rng.gen_range(room.x1 + 1, room.x2);
+		let y = rng.gen_range(room.y1 + 1, room.y2);
+
+		// Only place if the tile is not blocked
+		if !is_blocked(x, y, map, objects) {
+			let mut monster = pick_weighted(&content.monsters, |monster| monster.weight.at(level), rng).spawn(x, y);
+			if let Some(ref mut fighter) = monster.fighter {
+				fighter.max_hp = cmp::max(1, (fighter.max_hp as f32 * difficulty).round() as i32);
+				fighter.hp = fighter.max_hp;
+				fighter.power = (fighter.power as f32 * difficulty).round() as i32;
+			}
+
+			// some monsters carry an item, which stays on their remains
+			if rng.gen::<f32>() < MONSTER_LOOT_CHANCE {
+				monster.loot.push(random_item(x, y, &content.items, level, rng));
+			}
+			objects.push(monster);
+		}
+	}
+
+	// Choose random number of items
+	let max_items = MAX_ROOM_ITEMS + depth / LEVELS_PER_EXTRA_ITEM;
+	let num_items = rng.gen_range(0, max_items + 1);
+
+	for _ in 0..num_items {
+		// choose random spot for this item
+		let x = rng.gen_range(room.x1 + 1, room.x2);
+		let y = rng.gen_range(room.y1 + 1, room.y2);
+
+		// only place item if the tile is not blocked
+		if !is_blocked(x, y, map, objects) {
+			objects.push(random_item(x, y, &content.items, level, rng));
+		}
+	}
+
+	// food is only useful with the hunger clock enabled
+	if HUNGER_ENABLED && rng.gen::<f32>() < FOOD_CHANCE {
+		let x = rng.gen_range(room.x1 + 1, room.x2);
+		let y = rng.gen_range(room.y1 + 1, room.y2);
+		if !is_blocked(x, y, map, objects) {
+			let mut object = Object::new(x, y, '%', "ration of food", colors::DARK_SEPIA, false);
+			object.item = Some(Item::Food);
+			objects.push(object);
+		}
+	}
+}
+
+pub(crate) fn random_item(x: i32, y: i32, items: &[ItemDef], level: u32, rng: &mut StdRng) -> Object {
+	let mut item = pick_weighted(items, |item| item.weight.at(level), rng).spawn(x, y);
+	if let Some(ref mut equipment) = item.equipment {
+		if rng.gen::<f32>() < CURSE_CHANCE {
+			curse(equipment);
+		}
+	}
+	item
+}
+
+
+// difficulty scales how many monsters there are and how tough, 1.0 being as designed
+pub(crate) fn make_map(objects: &mut Vec<Object>, content: &Content, settings: &MapSettings, level: u32, seed: u64,
+				difficulty: f32) -> Map {
+	// each level of a seed always comes out the same
+	let mut rng: StdRng = SeedableRng::from_seed(&[seed as usize, level as usize][..]);
+
+	// fill map with wall tiles
+	let mut map = Map::new(MAP_WIDTH, MAP_HEIGHT, Tile::wall());
+	
+	let rooms = match settings.generator {
+		MapGenerator::Rooms => make_map_rooms(settings, &mut map, &mut rng),
+		MapGenerator::Bsp => make_map_bsp(settings, &mut map, &mut rng),
+	};
+	connect_rooms(&rooms, &mut map, &mut rng);
+
+	// put the player in the first room before filling it, so nothing spawns on top of them
+	let (start_x, start_y) = rooms[0].center();
+	objects[PLAYER].set_pos(start_x, start_y);
+
+	for (i, &room) in rooms.iter().enumerate() {
+		// Add content to the room
+		place_objects(room, &map, objects, content, level, difficulty, &mut rng);
+
+		// keep the starting room safe
+		if i > 0 {
+			place_spikes(room, &mut map, &mut rng);
+		}
+	}
+
+	place_doors(&rooms, &mut map, &mut rng);
+
+	debug_assert!({
+		let reachable = assert_connected(&map, (start_x, start_y));
+		rooms.iter().all(|room| {
+			let (x, y) = room.center();
+			reachable[map.index(x, y)]
+		})
+	}, "level {} of seed {} has a room that can't be reached", level, seed);
+
+	let last_room = rooms[rooms.len() - 1];
+	let (last_room_x, last_room_y) = last_room.center();
+	if level == DRAGON_LEVEL {
+		// the bottom of the dungeon has no way further down, only the dragon in the last room,
+		// kept out of the middle if that's where the player starts
+		let (dragon_x, dragon_y) = if rooms.len() > 1 {
+			(last_room_x, last_room_y)
+		} else {
+			(last_room.x2 - 1, last_room.y2 - 1)
+		};
+		objects.retain(|object| object.ai.is_none() || object.pos() != (dragon_x, dragon_y));
+		let def = content.monsters.iter().find(|monster| monster.name == DRAGON_NAME).unwrap();
+		let mut dragon = def.spawn(dragon_x, dragon_y);
+		if let Some(fighter) = dragon.fighter.as_mut() {
+			fighter.on_death = DeathCallBack::Dragon;
+		}
+		objects.push(dragon);
+	} else {
+		// the way down is in the last room made
+		let mut stairs = Object::new(last_room_x, last_room_y, '>', "stairs", colors::WHITE, false);
+		stairs.always_visible = true;
+		objects.push(stairs);
+	}
+
+	map
+}
+
+// Flood fill from start, returning which tiles can be walked to, indexed like map.tiles.
+// Doors count as open, and diagonal steps are allowed just like the player's
+pub(crate) fn assert_connected(map: &Map, start: (i32, i32)) -> Vec<bool> {
+	let mut reachable = vec![false; map.tiles.len()];
+	let mut queue = vec![start];
+	reachable[map.index(start.0, start.1)] = true;
+
+	while let Some((x, y)) = queue.pop() {
+		for dx in -1..=1 {
+			for dy in -1..=1 {
+				let (nx, ny) = (x + dx, y + dy);
+				if !map.in_bounds(nx, ny) || reachable[map.index(nx, ny)] {
+					continue;
+				}
+				let tile = map.get(nx, ny);
+				if !tile.blocked || tile.is_door {
+					reachable[map.index(nx, ny)] = true;
+					queue.push((nx, ny));
+				}
+			}
+		}
+	}
+
+	reachable
+}
+
+// Tunnel from any room the first room can't reach to the nearest room it can, until it reaches them all
+fn connect_rooms(rooms: &[Rect], map: &mut Map, rng: &mut StdRng) {
+	loop {
+		let reachable = assert_connected(map, rooms[0].center());
+		let (cut_off, connected): (Vec<_>, Vec<_>) = rooms.iter()
+			.map(Rect::center)
+			.partition(|&(x, y)| !reachable[map.index(x, y)]);
+
+		let (x, y) = match cut_off.first() {
+			Some(&center) => center,
+			None => return,
+		};
+		// the first room is always among the connected ones
+		let nearest = connected.into_iter()
+			.min_by_key(|&(other_x, other_y)| (other_x - x).pow(2) + (other_y - y).pow(2))
+			.unwrap();
+		create_l_tunnel(nearest, (x, y), map, rng);
+	}
+}
+
+// Rooms dropped at random wherever they don't overlap, each joined to the one before
+fn make_map_rooms(settings: &MapSettings, map: &mut Map, rng: &mut StdRng) -> Vec<Rect> {
+	let mut rooms: Vec<Rect> = vec![];
+
+	// try max_rooms times, and keep going for a while if that didn't give min_rooms
+	let mut attempts = 0;
+	while attempts < settings.max_rooms
+			|| (rooms.len() < settings.min_rooms as usize && attempts < ROOM_ATTEMPT_LIMIT) {
+		attempts += 1;
+
+		//random width and height
+		let w = rng.gen_range(settings.room_min_size, settings.room_max_size + 1);
+		let h = rng.gen_range(settings.room_min_size, settings.room_max_size + 1);
+
+		//random position without going out of the map boundaries
+		let x = rng.gen_range(0, MAP_WIDTH - w);
+		let y = rng.gen_range(0, MAP_HEIGHT - h);
+
+		let new_room = Rect::new(x, y, w, h);
+
+		// run through the other rooms and see if they intersect with this one
+		let failed = rooms.iter().any(|other_room| new_room.intersects_with(other_room));
+
+		if !failed {
+			// No intersections, so room is valid
+			let &(kind, _) = pick_weighted(ROOM_STYLES, |&(_, weight)| weight, rng);
+			create_room(new_room, kind, map);
+
+			// all rooms after the first:
+			// Connect it to the previous room with a tunnel
+			if let Some(prev_room) = rooms.last() {
+				let &(style, _) = pick_weighted(CORRIDOR_STYLES, |&(_, weight)| weight, rng);
+				create_corridor(style, prev_room.center(), new_room.center(), map, rng);
+			}
+
+			// finally append the new room to the list
+			rooms.push(new_room);
+		}
+	}
+
+	rooms
+}
+
+// Split the map in two again and again, put a room in each leaf, then join every pair of halves
+// with a tunnel on the way back up, so no room is ever cut off
+fn make_map_bsp(settings: &MapSettings, map: &mut Map, rng: &mut StdRng) -> Vec<Rect> {
+	let mut rooms: Vec<Rect> = vec![];
+
+	// tcod splits with its own generator, so seed that from ours to keep levels reproducible
+	let mut splitter = tcod::random::Rng::new_with_seed(tcod::random::Algo::MT, rng.gen());
+	// a leaf has to hold the smallest room plus the wall between it and the next leaf
+	let min_size = settings.room_min_size + 1;
+	let mut bsp = Bsp::new_with_size(0, 0, MAP_WIDTH, MAP_HEIGHT);
+	bsp.split_recursive(Some(&mut splitter), BSP_DEPTH, min_size, min_size, BSP_MAX_RATIO, BSP_MAX_RATIO);
+
+	// deepest nodes first, so both halves of a node have their rooms by the time it's visited
+	bsp.traverse(TraverseOrder::InvertedLevelOrder, |node| {
+		if node.is_leaf() {
+			let max_w = cmp::max(settings.room_min_size, cmp::min(settings.room_max_size, node.w - 1));
+			let max_h = cmp::max(settings.room_min_size, cmp::min(settings.room_max_size, node.h - 1));
+			let w = rng.gen_range(settings.room_min_size, max_w + 1);
+			let h = rng.gen_range(settings.room_min_size, max_h + 1);
+			let x = node.x + rng.gen_range(0, cmp::max(1, node.w - w));
+			let y = node.y + rng.gen_range(0, cmp::max(1, node.h - h));
+			let room = Rect::new(x, y, w, h);
+
+			let &(kind, _) = pick_weighted(ROOM_STYLES, |&(_, weight)| weight, rng);
+			create_room(room, kind, map);
+			rooms.push(room);
+		} else if let (Some(left), Some(right)) = (node.left(), node.right()) {
+			// join the two rooms, one from each half, that are closest to each other
+			let closest = |half: &Bsp, (to_x, to_y): (i32, i32)| {
+				rooms.iter()
+					.map(Rect::center)
+					.filter(|&(x, y)| half.contains(x, y))
+					.min_by_key(|&(x, y)| (x - to_x).pow(2) + (y - to_y).pow(2))
+			};
+			let right_center = (right.x + right.w / 2, right.y + right.h / 2);
+			if let Some(from) = closest(&left, right_center) {
+				if let Some(to) = closest(&right, from) {
+					let &(style, _) = pick_weighted(CORRIDOR_STYLES, |&(_, weight)| weight, rng);
+					create_corridor(style, from, to, map, rng);
+				}
+			}
+		}
+		true
+	});
+
+	rooms
+}
+
+// Hang doors in some of the one-tile gaps that tunnels made in the rooms' walls
+pub(crate) fn place_doors(rooms: &[Rect], map: &mut Map, rng: &mut StdRng) {
+	for room in rooms {
+		// each spot on the wall, with the wall tiles either side of it and the tile just outside
+		let mut gaps = vec![];
+		for x in (room.x1 + 1)..room.x2 {
+			gaps.push(((x, room.y1), (x - 1, room.y1), (x + 1, room.y1), (x, room.y1 - 1)));
+			gaps.push(((x, room.y2), (x - 1, room.y2), (x + 1, room.y2), (x, room.y2 + 1)));
+		}
+		for y in (room.y1 + 1)..room.y2 {
+			gaps.push(((room.x1, y), (room.x1, y - 1), (room.x1, y + 1), (room.x1 - 1, y)));
+			gaps.push(((room.x2, y), (room.x2, y - 1), (room.x2, y + 1), (room.x2 + 1, y)));
+		}
+
+		for ((x, y), side_a, side_b, outside) in gaps {
+			let is_floor = |(x, y): (i32, i32)| map.in_bounds(x, y) && !map.get(x, y).blocked;
+			let doorway = is_floor((x, y)) && !is_floor(side_a) && !is_floor(side_b) && is_floor(outside);
+			if doorway && rng.gen::<f32>() < DOOR_CHANCE {
+				*map.get_mut(x, y) = Tile::door();
+			}
+		}
+	}
+}
+
+pub(crate) fn place_spikes(room: Rect, map: &mut Map, rng: &mut StdRng) {
+	if rng.gen::<f32>() >= SPIKE_ROOM_CHANCE {
+		return;
+	}
+	let num_spikes = rng.gen_range(1, MAX_ROOM_SPIKES + 1);
+	for _ in 0..num_spikes {
+		let x = rng.gen_range(room.x1 + 1, room.x2);
+		let y = rng.gen_range(room.y1 + 1, room.y2);
+		map.get_mut(x, y).spikes = true;
+	}
+}
+
+pub(crate) fn create_room(room: Rect, kind: Option<TileType>, map: &mut Map) {
+	for x in (room.x1 + 1)..room.x2 {
+		for y in (room.y1 + 1)..room.y2 {
+			*map.get_mut(x, y) = Tile::floor(kind);
+		}
+	}
+}
+
+// Tunnels only turn rock into corridor, so where they cross a room it keeps its own floor
+pub(crate) fn dig_corridor(x: i32, y: i32, map: &mut Map) {
+	if map.get(x, y).blocked {
+		*map.get_mut(x, y) = Tile::floor(Some(TileType::Corridor));
+	}
+}
+
+pub(crate) fn create_corridor(style: Corridor, (x1, y1): (i32, i32), (x2, y2): (i32, i32), map: &mut Map,
+				   rng: &mut StdRng) {
+	match style {
+		Corridor::Straight => create_l_tunnel((x1, y1), (x2, y2), map, rng),
+		Corridor::Wide => {
+			// both Ls bend the same way, or they'd make a loop instead of one wide tunnel
+			let horizontal_first = rng.gen();
+			create_bent_tunnel((x1, y1), (x2, y2), horizontal_first, map);
+			// the second L right next to the first, on whichever side is still inside the border
+			let (side_x, side_y) = (if x1 + 1 < MAP_WIDTH - 1 && x2 + 1 < MAP_WIDTH - 1 { 1 } else { -1 },
+									if y1 + 1 < MAP_HEIGHT - 1 && y2 + 1 < MAP_HEIGHT - 1 { 1 } else { -1 });
+			create_bent_tunnel((x1 + side_x, y1 + side_y), (x2 + side_x, y2 + side_y), horizontal_first, map);
+		}
+		Corridor::Diagonal => {
+			let mut last_y = y1;
+			for (x, y) in tcod::line::Line::new((x1, y1), (x2, y2)) {
+				// fill in the corner of each diagonal step, so the tunnel is one solid path of floor
+				// rather than tiles that only touch at their corners
+				dig_corridor(x, last_y, map);
+				dig_corridor(x, y, map);
+				last_y = y;
+			}
+		}
+		Corridor::Winding => {
+			let (mut x, mut y) = (x1, y1);
+			dig_corridor(x, y, map);
+			for _ in 0..WINDING_STEP_LIMIT {
+				if (x, y) == (x2, y2) {
+					return;
+				}
+				let (dx, dy) = if rng.gen::<f32>() < WINDING_WANDER_CHANCE {
+					*rng.choose(&[(0, -1), (0, 1), (-1, 0), (1, 0)]).unwrap()
+				} else if x != x2 && (y == y2 || rng.gen()) {
+					((x2 - x).signum(), 0)
+				} else {
+					(0, (y2 - y).signum())
+				};
+				x = (x + dx).clamp(1, MAP_WIDTH - 2);
+				y = (y + dy).clamp(1, MAP_HEIGHT - 2);
+				dig_corridor(x, y, map);
+			}
+			// wandered for too long, so finish the job in a straight line
+			create_l_tunnel((x, y), (x2, y2), map, rng);
+		}
+	}
+}
+
+pub(crate) fn create_l_tunnel(from: (i32, i32), to: (i32, i32), map: &mut Map, rng: &mut StdRng) {
+	// flip a coin
+	create_bent_tunnel(from, to, rng.gen(), map);
+}
+
+pub(crate) fn create_bent_tunnel((x1, y1): (i32, i32), (x2, y2): (i32, i32), horizontal_first: bool, map: &mut Map) {
+	if horizontal_first {
+		//first move horizontally, then vertically
+		create_h_tunnel(x1, x2, y1, map);
+		create_v_tunnel(y1, y2, x2, map);
+	} else {
+		// first move vertically, then horizontally
+		create_v_tunnel(y1, y2, x1, map);
+		create_h_tunnel(x1, x2, y2, map);
+	}
+}
+
+pub(crate) fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map){
+	for x in cmp::min(x1, x2)..(cmp::max(x1, x2) + 1) {
+		dig_corridor(x, y, map);
+	}
+}
+
+pub(crate) fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map){
+	for y in cmp::min(y1, y2)..(cmp::max(y1, y2) + 1) {
+		dig_corridor(x, y, map);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SEEDS: u64 = 1000;
+
+	// Everything make_map places, the stairs included, can be walked to from the start on every seed
+	fn assert_all_reachable(settings: MapSettings) {
+		let content = load_content().unwrap();
+		for seed in 0..SEEDS {
+			let mut objects = vec![Object::new(0, 0, '@', "player", colors::WHITE, true)];
+			let map = make_map(&mut objects, &content, &settings, 1, seed, 1.0);
+			let reachable = assert_connected(&map, objects[PLAYER].pos());
+			for object in &objects {
+				assert!(reachable[map.index(object.x, object.y)],
+						"seed {}: the {} at {:?} can't be reached", seed, object.name, object.pos());
+			}
+		}
+	}
+
+	#[test]
+	fn random_rooms_are_connected() {
+		assert_all_reachable(NORMAL_DUNGEON);
+		assert_all_reachable(SPARSE_DUNGEON);
+		assert_all_reachable(DENSE_DUNGEON);
+	}
+
+	#[test]
+	fn bsp_rooms_are_connected() {
+		for &preset in &[NORMAL_DUNGEON, SPARSE_DUNGEON, DENSE_DUNGEON] {
+			let settings = MapSettings { generator: MapGenerator::Bsp, ..preset };
+			assert_all_reachable(settings);
+
+			// the tunnels between the halves already reach every room, before connect_rooms helps out
+			for seed in 0..SEEDS {
+				let mut rng: StdRng = SeedableRng::from_seed(&[seed as usize, 1][..]);
+				let mut map = Map::new(MAP_WIDTH, MAP_HEIGHT, Tile::wall());
+				let rooms = make_map_bsp(&settings, &mut map, &mut rng);
+				let reachable = assert_connected(&map, rooms[0].center());
+				assert!(rooms.iter().all(|room| reachable[map.index(room.center().0, room.center().1)]),
+						"seed {}: a BSP room is cut off", seed);
+			}
+		}
+	}
+}
@@ -0,0 +1,641 @@
+// Drawing the map and the panel, and the menus and message boxes
+
+use super::*;
+
+pub(crate) fn get_names_under_cursor(x: i32, y: i32, objects: &[Object], game: &Game, fov_map: &FovMap) -> String {
+	// create a list with the names of all objects at the cursor's coordinates that the player can see
+	let names = objects_at(x, y, objects)
+		.into_iter()
+		.filter(|&id| can_see(&objects[id], objects, game, fov_map))
+		.map(|id| {
+			// allies also show what they've been told to do
+			let object = &objects[id];
+			match object.ai.as_ref().and_then(Ai::ally_command) {
+				Some(command) => format!("{} ({})", object.name, command.description()),
+				None => object.name.clone(),
+			}
+		})
+		.collect::<Vec<_>>();
+
+	names.join(", ") // Join the names, separated by commas
+}
+
+// What the look cursor has found at (x, y): everything there the player can see, or the ground
+pub(crate) fn describe_at(x: i32, y: i32, objects: &[Object], game: &Game, fov_map: &FovMap) -> String {
+	if !fov_map.is_in_fov(x, y) {
+		return "You can't see what's there from here.".into();
+	}
+
+	let seen = objects_at(x, y, objects)
+		.into_iter()
+		.filter(|&id| can_see(&objects[id], objects, game, fov_map))
+		.map(|id| format!("{}: {}", objects[id].name, describe(id, &objects[id])))
+		.collect::<Vec<_>>();
+	if !seen.is_empty() {
+		return seen.join(". ") + ".";
+	}
+
+	let tile = game.map.get(x, y);
+	let ground = if tile.is_door {
+		if tile.open { "An open door." } else { "A closed door." }
+	} else if tile.blocked {
+		"Solid rock."
+	} else if tile.burning > 0 {
+		"The floor is on fire!"
+	} else if tile.spikes {
+		"Sharp spikes stick out of the floor."
+	} else if tile.net {
+		"Your net, waiting for something to walk into it."
+	} else {
+		"Bare floor."
+	};
+	ground.into()
+}
+
+// A few words about one thing the player is looking at
+fn describe(id: usize, object: &Object) -> String {
+	if id == PLAYER {
+		return "that's you".into();
+	}
+	if let Some(fighter) = object.fighter {
+		if let Some(command) = object.ai.as_ref().and_then(Ai::ally_command) {
+			return format!("your ally, {}", command.description());
+		}
+		let mut text = String::from(if fighter.hp * 3 <= fighter.max_hp {
+			"badly wounded"
+		} else if fighter.hp < fighter.max_hp {
+			"wounded"
+		} else {
+			"unhurt"
+		});
+		match object.ai {
+			Some(Ai::Confused{..}) => text += ", confused",
+			Some(Ai::Breather{aim: Some(_)}) => text += ", drawing a deep breath",
+			_ => {}
+		}
+		if object.held_turns > 0 {
+			text += ", caught in a net";
+		}
+		return text;
+	}
+	match (object.item, object.equipment) {
+		(_, Some(equipment)) if equipment.equipped => format!("worn on the {}", equipment.slot.name()),
+		(_, Some(equipment)) => format!("can be worn on the {}", equipment.slot.name()),
+		(Some(_), None) => "you could pick it up".into(),
+		(None, _) if object.always_visible => "the way further down".into(),
+		(None, _) if !object.loot.is_empty() => "something is still on them".into(),
+		(None, _) => "long dead".into(),
+	}
+}
+
+pub(crate) fn render_all(tcod: &mut Tcod, objects: &[Object], game: &mut Game,
+		 fov_recompute: bool){
+	if fov_recompute {
+		// recompute FOV if needed
+		let player = &objects[0];
+		tcod.fov.compute_fov(player.x, player.y, TORCH_RADIUS, FOV_LIGHT_WALLS, FOV_ALGO);
+	}
+
+	//go through all the tiles and set their background color
+	for y in 0..MAP_HEIGHT {
+		for x in 0..MAP_WIDTH {
+			let visible = tcod.fov.is_in_fov(x, y);
+			let wall = game.map.get(x, y).block_sight;
+			let burning = game.map.get(x, y).burning > 0;
+			let spikes = game.map.get(x, y).spikes;
+			let net = game.map.get(x, y).net;
+			let color = match (visible, wall, burning) {
+				// burning floor
+				(false, false, true) => colors::DARKER_ORANGE,
+				(true, false, true) => colors::ORANGE,
+				// spikes
+				(false, false, _) if spikes => COLOR_DARK_SPIKES,
+				(true, false, _) if spikes => COLOR_LIGHT_SPIKES,
+				// nets the player laid
+				(false, false, _) if net => COLOR_DARK_NET,
+				(true, false, _) if net => COLOR_LIGHT_NET,
+				// outside of field of view:
+				(false, true, _) => COLOR_DARK_WALL,
+				(false, false, _) => ground_color(game.map.get(x, y).kind, false),
+				// inside fov:
+				(true, true, _) => COLOR_LIGHT_WALL,
+				(true, false, _) => ground_color(game.map.get(x, y).kind, true),
+			};
+			// grey out what the player only remembers, so it stands apart from what they can see
+			let color = if visible { color } else { fog_color(color) };
+
+			let explored = &mut game.map.get_mut(x, y).explored;
+			if visible {
+				// since it's visible, explore it
+				*explored = true;
+			}
+			if visible || (TILE_MEMORY && *explored) {
+				// show explored tiles only, or only visible ones without tile memory
+				tcod.con.set_char_background(x, y, color, BackgroundFlag::Set);
+				let tile = game.map.get(x, y);
+				if tile.is_door {
+					let door_color = if visible { COLOR_DOOR } else { fog_color(COLOR_DOOR) };
+					tcod.con.set_default_foreground(door_color);
+					tcod.con.put_char(x, y, if tile.open { '/' } else { '+' }, BackgroundFlag::None);
+				}
+			}
+		}
+	}
+
+	// living monsters are also drawn out of sight while they're being detected
+	let detecting = game.detect_turns > 0;
+	let mut to_draw: Vec<_> = objects.iter()
+		.filter(|o| can_see(o, objects, game, &tcod.fov) || (detecting && o.alive && o.ai.is_some())
+				|| (o.always_visible && TILE_MEMORY && game.map.get(o.x, o.y).explored))
+		.collect();
+	
+	// sort so that non-blocking objects come first
+	to_draw.sort_by(|o1, o2| { o1.blocks.cmp(&o2.blocks) });
+	
+	// draw the objects in the list
+	for object in &to_draw {
+		object.draw(&mut tcod.con, tcod.tileset);
+	}
+
+	// show where a monster that has drawn breath is about to breathe
+	let danger: Vec<(i32, i32)> = objects.iter()
+		.filter(|o| can_see(o, objects, game, &tcod.fov))
+		.filter_map(|o| match o.ai {
+			Some(Ai::Breather{aim: Some(target)}) => Some(breath_line(o.pos(), target, &game.map)),
+			_ => None,
+		})
+		.flatten()
+		.filter(|&(x, y)| tcod.fov.is_in_fov(x, y))
+		.collect();
+	for (x, y) in danger {
+		tcod.con.set_char_background(x, y, COLOR_DANGER, BackgroundFlag::Set);
+	}
+
+	if game.debug_overlay {
+		draw_debug_overlay(tcod, objects);
+	}
+
+	// highlight the keyboard-look cursor
+	if let Some((x, y)) = tcod.look_cursor {
+		tcod.con.set_char_background(x, y, colors::LIGHT_GREY, BackgroundFlag::Set);
+	}
+
+
+	// blit the contents of "con" to the root console
+	blit(&tcod.con, (0, 0), (MAP_WIDTH, MAP_HEIGHT), &mut tcod.root, (0, 0), 1.0, 1.0);
+
+	if tcod.minimap {
+		render_minimap(tcod, &game.map, objects);
+	}
+
+
+	// Prepare to render the GUI panel
+	tcod.panel.set_default_background(colors::BLACK);
+	tcod.panel.clear();
+
+	// Print the game messages, one line at a time
+	let layout = &tcod.layout;
+	let mut y = layout.msg_height;
+	for &(ref msg, color) in game.log.iter_tail(layout.msg_height as usize).rev() {
+		let msg_height = tcod.panel.get_height_rect(layout.msg_x, y, layout.msg_width, 0, msg);
+		y -= msg_height;
+		if y < 0 {
+			break;
+		}
+
+		tcod.panel.set_default_foreground(color);
+		tcod.panel.print_rect(layout.msg_x, y, layout.msg_width, 0, msg);
+	}
+
+	// show the player's stats, one bar per row under the names line
+	let hp = objects[PLAYER].fighter.map_or(0,|f| f.hp);
+	let max_hp = objects[PLAYER].max_hp(game);
+	let mut bars = vec![("HP", hp, max_hp, colors::LIGHT_RED, colors::DARKER_RED)];
+	if !game.spells.is_empty() {
+		bars.push(("Mana", game.mana, game.max_mana, colors::LIGHT_BLUE, colors::DARKER_BLUE));
+	}
+	if STAMINA_ENABLED {
+		bars.push(("Stamina", game.stamina, MAX_STAMINA, colors::LIGHT_GREEN, colors::DARKER_GREEN));
+	}
+	if HUNGER_ENABLED {
+		bars.push(("Food", game.satiation, MAX_SATIATION, colors::LIGHT_SEPIA, colors::DARKER_SEPIA));
+	}
+	for (row, &(name, value, maximum, bar_color, back_color)) in bars.iter().enumerate() {
+		let y = 1 + row as i32;
+		if y >= layout.panel_height {
+			break;
+		}
+		render_bar(&mut tcod.panel, 1, y, layout.bar_width, name, value, maximum, bar_color, back_color);
+	}
+
+	// flash a warning under the bars while the player is nearly dead
+	let warning_y = 1 + bars.len() as i32;
+	if is_low_hp(&objects[PLAYER], game) && warning_y < layout.panel_height {
+		let flash_on = tcod::system::get_elapsed_time().as_millis() % (2 * LOW_HP_FLASH_MILLIS) < LOW_HP_FLASH_MILLIS;
+		tcod.panel.set_default_foreground(if flash_on { colors::RED } else { colors::DARKER_RED });
+		tcod.panel.print_ex(1 + layout.bar_width / 2, warning_y, BackgroundFlag::None, TextAlignment::Center,
+						"*** LOW HEALTH ***");
+	}
+
+	// the dungeon level, then one short tag per effect on the player with the turns it has left
+	let effects = [("Dlvl", game.dungeon_level as i32, colors::WHITE),
+				   ("Det", game.detect_turns, colors::LIGHT_CYAN),
+				   ("SeeInv", game.see_invisible_turns, colors::CYAN)];
+	let status_y = warning_y + 1;
+	let mut status_x = 1;
+	for &(tag, turns, color) in effects.iter().filter(|&&(_, turns, _)| turns > 0) {
+		let text = format!("{}:{}", tag, turns);
+		if status_y >= layout.panel_height || status_x + text.len() as i32 > layout.bar_width + 1 {
+			break;
+		}
+		tcod.panel.set_default_foreground(color);
+		tcod.panel.print_ex(status_x, status_y, BackgroundFlag::None, TextAlignment::Left, &text);
+		status_x += text.len() as i32 + 1;
+	}
+
+	// display names of objects under the mouse, or the keyboard cursor when looking with it
+	let look = tcod.look_cursor.or_else(|| screen_to_world(&tcod.mouse, &game.map));
+	if let Some((look_x, look_y)) = look {
+		tcod.panel.set_default_foreground(colors::LIGHT_GREY);
+		tcod.panel.print_ex(1, 0, BackgroundFlag::None, TextAlignment::Left,
+						get_names_under_cursor(look_x, look_y, objects, game, &tcod.fov));
+	}
+
+	// blit the contents of 'panel' to the root console
+	blit(&tcod.panel, (0, 0), (layout.panel_width, layout.panel_height),
+		&mut tcod.root, (0, layout.panel_y), 1.0, 1.0);
+}
+
+// The A* paths the monsters planned this turn, and the visible tiles at the edge of the FOV
+fn draw_debug_overlay(tcod: &mut Tcod, objects: &[Object]) {
+	for &(x, y) in objects.iter().flat_map(|object| &object.path) {
+		tcod.con.set_char_background(x, y, COLOR_DEBUG_PATH, BackgroundFlag::Set);
+	}
+	for y in 0..MAP_HEIGHT {
+		for x in 0..MAP_WIDTH {
+			let edge = tcod.fov.is_in_fov(x, y) && [(-1, 0), (1, 0), (0, -1), (0, 1)].iter()
+				.map(|&(dx, dy)| (x + dx, y + dy))
+				.any(|(nx, ny)| nx >= 0 && ny >= 0 && nx < MAP_WIDTH && ny < MAP_HEIGHT && !tcod.fov.is_in_fov(nx, ny));
+			if edge {
+				tcod.con.set_char_background(x, y, COLOR_DEBUG_FOV, BackgroundFlag::Set);
+			}
+		}
+	}
+}
+
+// The floor's own colour, lit or dark
+fn ground_color(kind: Option<TileType>, lit: bool) -> Color {
+	let (dark, light) = match kind {
+		None => (COLOR_DARK_GROUND, COLOR_LIGHT_GROUND),
+		Some(TileType::Corridor) => (COLOR_DARK_CORRIDOR, COLOR_LIGHT_CORRIDOR),
+		Some(TileType::Cave) => (COLOR_DARK_CAVE, COLOR_LIGHT_CAVE),
+		Some(TileType::SpecialRoom) => (COLOR_DARK_SPECIAL_ROOM, COLOR_LIGHT_SPECIAL_ROOM),
+	};
+	if lit { light } else { dark }
+}
+
+pub(crate) fn fog_color(color: Color) -> Color {
+	let grey = ((color.r as u32 + color.g as u32 + color.b as u32) / 3) as u8;
+	colors::lerp(color, Color::new(grey, grey, grey), FOG_DESATURATION)
+}
+
+// A shrunken overview of what has been explored, over the top-right corner of the map. Without
+// tile memory it only has what is in view, like the map itself
+pub(crate) fn render_minimap(tcod: &mut Tcod, map: &Map, objects: &[Object]) {
+	let width = (MAP_WIDTH + MINIMAP_SCALE - 1) / MINIMAP_SCALE;
+	// stop short of the panel so the messages are never covered
+	let height = cmp::min((MAP_HEIGHT + MINIMAP_SCALE - 1) / MINIMAP_SCALE, tcod.layout.panel_y);
+	let mut minimap = Offscreen::new(width, height);
+
+	for y in 0..height {
+		for x in 0..width {
+			// a square counts as floor if any explored tile in it is, so tunnels don't vanish
+			let mut floor = false;
+			let mut wall = false;
+			for tile_y in (y * MINIMAP_SCALE)..cmp::min((y + 1) * MINIMAP_SCALE, MAP_HEIGHT) {
+				for tile_x in (x * MINIMAP_SCALE)..cmp::min((x + 1) * MINIMAP_SCALE, MAP_WIDTH) {
+					let tile = map.get(tile_x, tile_y);
+					if tile.explored && (TILE_MEMORY || tcod.fov.is_in_fov(tile_x, tile_y)) {
+						if tile.blocked && !tile.is_door {
+							wall = true;
+						} else {
+							floor = true;
+						}
+					}
+				}
+			}
+			if floor {
+				minimap.put_char_ex(x, y, '.', COLOR_LIGHT_GROUND, colors::BLACK);
+			} else if wall {
+				minimap.put_char_ex(x, y, '#', COLOR_LIGHT_WALL, colors::BLACK);
+			}
+		}
+	}
+
+	let (player_x, player_y) = objects[PLAYER].pos();
+	minimap.put_char_ex(player_x / MINIMAP_SCALE, player_y / MINIMAP_SCALE, '@', colors::WHITE, colors::BLACK);
+
+	let x = cmp::min(MAP_WIDTH, tcod.root.width()) - width;
+	blit(&minimap, (0, 0), (width, height), &mut tcod.root, (x, 0), 1.0, 0.7);
+}
+
+// Shows the last few messages above the panel until Tab is released
+pub(crate) fn peek_messages(tcod: &mut Tcod, game: &Game) {
+	use tcod::input::KeyCode::Tab;
+
+	let width = tcod.layout.panel_width;
+	let mut window = Offscreen::new(width, PEEK_HEIGHT);
+	let mut y = PEEK_HEIGHT;
+	for &(ref msg, color) in game.log.iter_tail(PEEK_HEIGHT as usize).rev() {
+		let msg_height = window.get_height_rect(1, y, width - 2, 0, msg);
+		y -= msg_height;
+		if y < 0 {
+			break;
+		}
+
+		window.set_default_foreground(color);
+		window.print_rect(1, y, width - 2, 0, msg);
+	}
+	blit(&window, (0, 0), (width, PEEK_HEIGHT), &mut tcod.root,
+		(0, tcod.layout.panel_y - PEEK_HEIGHT), 1.0, 0.8);
+
+	loop {
+		tcod.root.flush();
+		match tcod.input.check_for_event(input::KEY_RELEASE) {
+			Some(Event::Key(Key { code: Tab, .. })) => break,
+			_ if tcod.root.window_closed() => break,
+			_ => {}
+		}
+	}
+}
+
+// The whole message log in its colours, newest at the bottom, scrolled with the arrow and page keys
+// until any other key is pressed
+pub(crate) fn show_messages(tcod: &mut Tcod, game: &Game) {
+	use tcod::input::KeyCode::{Up, Down, PageUp, PageDown};
+
+	// lay every message out once on a page tall enough to hold them all
+	let width = HISTORY_WIDTH - 2;
+	let scratch = Offscreen::new(width, SCREEN_HEIGHT);
+	let heights: Vec<i32> = game.log.iter_tail(game.log.len())
+		.map(|(msg, _)| scratch.get_height_rect(0, 0, width, 0, msg))
+		.collect();
+	let total: i32 = heights.iter().sum();
+	let mut page = Offscreen::new(width, cmp::max(total, 1));
+	let mut y = 0;
+	for (&(ref msg, color), height) in game.log.iter_tail(game.log.len()).zip(heights) {
+		page.set_default_foreground(color);
+		page.print_rect(0, y, width, 0, msg);
+		y += height;
+	}
+
+	// keep what's underneath, so each scroll draws the window over the game rather than the last frame
+	let mut behind = Offscreen::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+	blit(&tcod.root, (0, 0), (SCREEN_WIDTH, SCREEN_HEIGHT), &mut behind, (0, 0), 1.0, 1.0);
+
+	let shown = total.clamp(1, HISTORY_HEIGHT - 2);
+	let x = SCREEN_WIDTH / 2 - HISTORY_WIDTH / 2;
+	let y = SCREEN_HEIGHT / 2 - (shown + 2) / 2;
+	// start at the newest messages
+	let mut top = cmp::max(total - shown, 0);
+	loop {
+		let mut window = Offscreen::new(HISTORY_WIDTH, shown + 2);
+		window.set_default_foreground(colors::WHITE);
+		window.print_ex(0, 0, BackgroundFlag::None, TextAlignment::Left, "Messages (any other key to close)");
+		blit(&page, (0, top), (width, shown), &mut window, (1, 2), 1.0, 1.0);
+
+		blit(&behind, (0, 0), (SCREEN_WIDTH, SCREEN_HEIGHT), &mut tcod.root, (0, 0), 1.0, 1.0);
+		blit(&window, (0, 0), (HISTORY_WIDTH, shown + 2), &mut tcod.root, (x, y), 1.0, 0.7);
+		tcod.root.flush();
+
+		top = match tcod.input.wait_for_keypress(&mut tcod.root) {
+			Key { code: Up, .. } => top - 1,
+			Key { code: Down, .. } => top + 1,
+			Key { code: PageUp, .. } => top - shown,
+			Key { code: PageDown, .. } => top + shown,
+			_ => break,
+		};
+		top = cmp::max(cmp::min(top, total - shown), 0);
+		if tcod.root.window_closed() {
+			break;
+		}
+	}
+}
+
+// The run's journal, newest entries last, until any key is pressed
+pub(crate) fn show_journal(tcod: &mut Tcod, game: &Game) {
+	let height = cmp::min(game.journal.len() as i32, SCREEN_HEIGHT - 2) + 2;
+	let mut window = Offscreen::new(JOURNAL_WIDTH, height);
+	window.set_default_foreground(colors::WHITE);
+	window.print_ex(0, 0, BackgroundFlag::None, TextAlignment::Left, "Journal (any key to close)");
+	let shown = game.journal.len().saturating_sub((height - 2) as usize);
+	for (row, entry) in game.journal[shown..].iter().enumerate() {
+		window.print_ex(0, 2 + row as i32, BackgroundFlag::None, TextAlignment::Left,
+					format!("Turn {:>5}: {}", entry.turn, entry.text));
+	}
+
+	let x = SCREEN_WIDTH / 2 - JOURNAL_WIDTH / 2;
+	let y = SCREEN_HEIGHT / 2 - height / 2;
+	blit(&window, (0, 0), (JOURNAL_WIDTH, height), &mut tcod.root, (x, y), 1.0, 0.7);
+	tcod.root.flush();
+	tcod.input.wait_for_keypress(&mut tcod.root);
+}
+
+pub(crate) fn render_bar(panel: &mut Offscreen,
+				x: i32,
+				y: i32,
+				total_width: i32,
+				name: &str,
+				value: i32,
+				maximum: i32,
+				bar_color: Color,
+				back_color: Color)
+{
+	// render a bar (hp, exp, etc) First calculate width of bar
+	let bar_width = (value as f32 / maximum as f32 * total_width as f32) as i32;
+
+	// render the background first
+	panel.set_default_background(back_color);
+	panel.rect(x, y, total_width, 1, false, BackgroundFlag::Screen);
+
+	// now render the bar on top
+	panel.set_default_background(bar_color);
+	if bar_width > 0 {
+		panel.rect(x, y, bar_width, 1, false, BackgroundFlag::Screen);
+	}
+
+	// Centered text with the values
+	panel.set_default_foreground(colors::WHITE);
+	panel.print_ex(x + total_width / 2, y, BackgroundFlag::None, TextAlignment::Center,
+				&format!("{}: {}/{}", name, value, maximum));
+}
+
+pub(crate) fn menu<T: AsRef<str>>(header: &str, options: &[T], width: i32,
+						tcod: &mut Tcod) -> Option<usize> {
+	use tcod::input::KeyCode::{PageDown, PageUp};
+
+	// options are shown in pages of 26, one for each letter
+	let num_pages = cmp::max(1, options.len().div_ceil(MENU_PAGE_SIZE));
+	let mut page = 0;
+
+	loop {
+		let first = page * MENU_PAGE_SIZE;
+		let page_options = &options[first..cmp::min(first + MENU_PAGE_SIZE, options.len())];
+
+		// tell the player how to reach the other pages, if there are any
+		let header = if num_pages > 1 {
+			format!("{}(page {}/{}, PageUp/PageDown for more)\n", header, page + 1, num_pages)
+		} else {
+			header.to_string()
+		};
+
+		// calculate total height for the header (after auto-wrap) and one line per option;
+		// every page is as tall as a full one so a short last page covers the previous one
+		let header_height = tcod.root.get_height_rect(0, 0, width, SCREEN_HEIGHT, &header);
+		let height = cmp::min(options.len(), MENU_PAGE_SIZE) as i32 + header_height;
+
+		// create off-screen console that represents the menu's window
+		let mut window = Offscreen::new(width, height);
+
+		// print the header, with auto-wrap
+		window.set_default_foreground(colors::WHITE);
+		window.print_rect_ex(0, 0, width, height, BackgroundFlag::None, TextAlignment::Left, &header);
+
+		// print all the options
+		// enum method on iterator gets index for each loop through
+		// and uses that to display the corresponding option letter
+		for (index, option_text) in page_options.iter().enumerate() {
+			let menu_letter= (b'a' + index as u8) as char;
+			let text = format!("({}) {}", menu_letter, option_text.as_ref());
+			window.print_ex(0, header_height + index as i32,
+						BackgroundFlag::None, TextAlignment::Left, text);
+		}
+
+		// blit the contents of "window" to the root console
+		let x = SCREEN_WIDTH / 2 - width / 2;
+		let y = SCREEN_HEIGHT / 2 - height / 2;
+		tcod::console::blit(&mut window, (0, 0), (width,height), &mut tcod.root, (x, y), 1.0, 0.7);
+
+		// present the root console to the player and wait for a keypress
+		tcod.root.flush();
+		let key = tcod.input.wait_for_keypress(&mut tcod.root);
+
+		match key.code {
+			PageDown if page + 1 < num_pages => page += 1,
+			PageUp if page > 0 => page -= 1,
+			PageDown | PageUp => {}
+			_ => {
+				// convert ASCII code to an index; if it corresponds to an option on
+				// this page, return its position in the full list
+				if key.printable.is_alphabetic() {
+					let index = key.printable.to_ascii_lowercase() as usize - 'a' as usize;
+					if index < page_options.len() {
+						return Some(first + index);
+					}
+				}
+				return None;
+			}
+		}
+	}
+}
+
+// A menu with nothing to pick, closed by any key
+pub(crate) fn msgbox(text: &str, width: i32, tcod: &mut Tcod) {
+	let options: &[&str] = &[];
+	menu(text, options, width, tcod);
+}
+
+// A yes or no question, answered with y or n; Escape counts as no, closing the window as yes
+pub(crate) fn confirm(question: &str, width: i32, tcod: &mut Tcod) -> bool {
+	use tcod::input::KeyCode::Escape;
+
+	let text = format!("{}\n(y) Yes\n(n) No", question);
+	let height = tcod.root.get_height_rect(0, 0, width, SCREEN_HEIGHT, &text);
+	let mut window = Offscreen::new(width, height);
+	window.set_default_foreground(colors::WHITE);
+	window.print_rect_ex(0, 0, width, height, BackgroundFlag::None, TextAlignment::Left, &text);
+
+	let x = SCREEN_WIDTH / 2 - width / 2;
+	let y = SCREEN_HEIGHT / 2 - height / 2;
+	tcod::console::blit(&mut window, (0, 0), (width, height), &mut tcod.root, (x, y), 1.0, 0.7);
+	tcod.root.flush();
+
+	// anything else is ignored, so a stray key doesn't answer for the player
+	loop {
+		let key = tcod.input.wait_for_keypress(&mut tcod.root);
+		// closing the window is as good as a yes: the game is being shut down anyway
+		if tcod.root.window_closed() {
+			return true;
+		}
+		match key.printable.to_ascii_lowercase() {
+			'y' => return true,
+			'n' => return false,
+			_ if key.code == Escape => return false,
+			_ => {}
+		}
+	}
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum MainMenuChoice {
+	Continue,
+	NewGame,
+	Quit,
+}
+
+// The title screen, until the player picks something; Continue is only offered with a save to continue
+pub(crate) fn main_menu(can_continue: bool, tcod: &mut Tcod) -> MainMenuChoice {
+	use MainMenuChoice::*;
+	let mut choices = vec![];
+	if can_continue {
+		choices.push(("Continue", Continue));
+	}
+	choices.push(("New game", NewGame));
+	choices.push(("Quit", Quit));
+	let names: Vec<_> = choices.iter().map(|&(name, _)| name).collect();
+
+	let choice = loop {
+		tcod.root.set_default_foreground(colors::LIGHT_YELLOW);
+		tcod.root.clear();
+		tcod.root.print_ex(SCREEN_WIDTH / 2, SCREEN_HEIGHT / 2 - 6, BackgroundFlag::None, TextAlignment::Center,
+					"DRAGONSLAYER");
+		tcod.root.print_ex(SCREEN_WIDTH / 2, SCREEN_HEIGHT / 2 - 4, BackgroundFlag::None, TextAlignment::Center,
+					"Slay the dragon at the bottom of the dungeon");
+		match menu("", &names, MAIN_MENU_WIDTH, tcod) {
+			Some(index) => break choices[index].1,
+			None if tcod.root.window_closed() => break Quit,
+			None => {}
+		}
+	};
+	// leave a blank screen for the game to draw on
+	tcod.root.set_default_foreground(colors::WHITE);
+	tcod.root.clear();
+	choice
+}
+
+pub(crate) fn inventory_menu(inventory: &[Object], header: &str, tcod: &mut Tcod) -> Option<usize> {
+	// have a menu with each item of the inventory as an option
+	let options = if inventory.len() == 0 {
+		vec!["Inventory is empty".into()]
+	} else {
+		inventory.iter().map(|item| {
+			// show where worn equipment is
+			match item.equipment {
+				// a curse only shows once the thing is on
+				Some(equipment) if equipment.equipped && equipment.cursed =>
+					format!("{} (on {}, cursed)", item.name, equipment.slot.name()),
+				Some(equipment) if equipment.equipped => format!("{} (on {})", item.name, equipment.slot.name()),
+				_ => item.name.clone(),
+			}
+		}).collect()
+	};
+
+	let inventory_index = menu(header, &options, INVENTORY_WIDTH, tcod);
+
+	// if an item was chose, return it
+	if inventory.len() > 0 {
+		inventory_index
+	} else {
+		None
+	}
+}
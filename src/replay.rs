@@ -0,0 +1,417 @@
+// Recording every input of a run to a file, and playing a recording back against the same seed
+
+use super::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::io::{BufWriter, Write};
+use tcod::input::{EventFlags, KeyCode};
+
+// The options that change how a run plays, so a recording only plays back with the same ones
+const REPLAY_OPTIONS: &[&str] = &["--tutorial", "--practice", "--debug", "--dungeon=", "--generator="];
+
+// One line of a recording, in the order the game asked for them
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) enum Recorded {
+	Start { seed: u64, options: Vec<String> },
+	Seed(u64), // a new run was started from this seed
+	Key(RecordedKey),
+	Mouse(#[serde(with = "MouseDef")] Mouse),
+	Idle(u32), // this many looks at the input in a row found nothing
+	Turn(Checkpoint), // a turn ended with the game like this
+}
+
+// Enough of the game to tell when a replay has gone its own way
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Checkpoint {
+	turn: u32,
+	dungeon_level: u32,
+	player: (i32, i32),
+	hp: i32,
+	objects: u64, // a hash of where every object is and how it's doing
+}
+
+impl Checkpoint {
+	pub(crate) fn new(objects: &[Object], game: &Game) -> Self {
+		let mut hasher = DefaultHasher::new();
+		for object in objects {
+			(object.x, object.y, object.alive, object.fighter.map(|f| f.hp)).hash(&mut hasher);
+		}
+		Checkpoint {
+			turn: game.stats.turns,
+			dungeon_level: game.dungeon_level,
+			player: objects[PLAYER].pos(),
+			hp: objects[PLAYER].fighter.map_or(0, |f| f.hp),
+			objects: hasher.finish(),
+		}
+	}
+}
+
+// tcod's Key has a private field, so it's copied into one serde can build
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RecordedKey {
+	#[serde(with = "KeyCodeDef")]
+	code: KeyCode,
+	printable: char,
+	pressed: bool,
+	left_alt: bool,
+	left_ctrl: bool,
+	right_alt: bool,
+	right_ctrl: bool,
+	shift: bool,
+	alt: bool,
+	ctrl: bool,
+}
+
+impl From<Key> for RecordedKey {
+	fn from(key: Key) -> Self {
+		RecordedKey {
+			code: key.code,
+			printable: key.printable,
+			pressed: key.pressed,
+			left_alt: key.left_alt,
+			left_ctrl: key.left_ctrl,
+			right_alt: key.right_alt,
+			right_ctrl: key.right_ctrl,
+			shift: key.shift,
+			alt: key.alt,
+			ctrl: key.ctrl,
+		}
+	}
+}
+
+impl From<RecordedKey> for Key {
+	fn from(recorded: RecordedKey) -> Self {
+		let mut key = Key::default();
+		key.code = recorded.code;
+		key.printable = recorded.printable;
+		key.pressed = recorded.pressed;
+		key.left_alt = recorded.left_alt;
+		key.left_ctrl = recorded.left_ctrl;
+		key.right_alt = recorded.right_alt;
+		key.right_ctrl = recorded.right_ctrl;
+		key.shift = recorded.shift;
+		key.alt = recorded.alt;
+		key.ctrl = recorded.ctrl;
+		key
+	}
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "KeyCode")]
+enum KeyCodeDef {
+	NoKey, Escape, Backspace, Tab, Enter, Shift, Control, Alt, Pause, CapsLock, PageUp, PageDown, End, Home,
+	Up, Left, Right, Down, PrintScreen, Insert, Delete, LeftWin, RightWin, Apps,
+	Number0, Number1, Number2, Number3, Number4, Number5, Number6, Number7, Number8, Number9,
+	NumPad0, NumPad1, NumPad2, NumPad3, NumPad4, NumPad5, NumPad6, NumPad7, NumPad8, NumPad9,
+	NumPadAdd, NumPadSubtract, NumPadDivide, NumPadMultiply, NumPadDecimal, NumPadEnter,
+	F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12, NumLock, ScrollLock, Spacebar, Char, Text,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Mouse")]
+struct MouseDef {
+	x: isize,
+	y: isize,
+	dx: isize,
+	dy: isize,
+	cx: isize,
+	cy: isize,
+	dcx: isize,
+	dcy: isize,
+	lbutton: bool,
+	rbutton: bool,
+	mbutton: bool,
+	lbutton_pressed: bool,
+	rbutton_pressed: bool,
+	mbutton_pressed: bool,
+	wheel_up: bool,
+	wheel_down: bool,
+}
+
+// What the game was after when it asked for the next recorded entry
+#[derive(Clone, Copy, PartialEq)]
+enum Want {
+	Event,
+	Key,
+	Seed,
+	Turn,
+}
+
+impl Want {
+	fn doing(self) -> &'static str {
+		match self {
+			Want::Event => "looked for input",
+			Want::Key => "waited for a key",
+			Want::Seed => "started a new run",
+			Want::Turn => "ended a turn",
+		}
+	}
+}
+
+// Where the game's input comes from. Everything that reads the keyboard or mouse goes through here,
+// so a recording holds every input in order and a replay can hand them back the same way
+pub(crate) enum Input {
+	Live,
+	Recording {
+		path: String,
+		file: BufWriter<File>,
+		idle: u32, // looks that found nothing, not written yet
+	},
+	Playing {
+		entries: VecDeque<Recorded>,
+		turn: u32, // the last turn that matched the recording
+		ended: Option<Result<(), String>>, // Err says how the replay diverged
+	},
+}
+
+// --record=<file> records the run to the file, and --replay=<file> plays one back, on the seed it
+// was recorded with. Returns the input along with the seed for the run
+pub(crate) fn input_from_args() -> Result<(Input, u64), String> {
+	let path_of = |option: &str| env::args().find(|arg| arg.starts_with(option)).map(|arg| arg[option.len()..].to_string());
+	if let Some(path) = path_of("--replay=") {
+		return Input::play(&path);
+	}
+	let seed = seed_from_args()?;
+	let input = match path_of("--record=") {
+		Some(path) => Input::record(path, seed)?,
+		None => Input::Live,
+	};
+	Ok((input, seed))
+}
+
+fn replay_options() -> Vec<String> {
+	env::args().filter(|arg| REPLAY_OPTIONS.iter().any(|option| arg.starts_with(option))).collect()
+}
+
+impl Input {
+	fn record(path: String, seed: u64) -> Result<Self, String> {
+		let file = File::create(&path).map_err(|error| format!("Could not create {}: {}", path, error))?;
+		let mut input = Input::Recording { path, file: BufWriter::new(file), idle: 0 };
+		input.write(Recorded::Start { seed, options: replay_options() });
+		Ok(input)
+	}
+
+	fn play(path: &str) -> Result<(Self, u64), String> {
+		let text = fs::read_to_string(path).map_err(|error| format!("Could not read {}: {}", path, error))?;
+		let mut entries = text.lines()
+			.enumerate()
+			.map(|(line, entry)| serde_json::from_str(entry)
+				.map_err(|error| format!("{} is malformed on line {}: {}", path, line + 1, error)))
+			.collect::<Result<VecDeque<Recorded>, String>>()?;
+		let seed = match entries.pop_front() {
+			Some(Recorded::Start { seed, options }) => {
+				if options != replay_options() {
+					return Err(format!("{} was recorded with the options [{}], so it has to be played back with them.",
+						path, options.join(" ")));
+				}
+				seed
+			}
+			_ => return Err(format!("{} is not a recording.", path)),
+		};
+		println!("Replaying {} from seed {}", path, seed);
+		Ok((Input::Playing { entries, turn: 0, ended: None }, seed))
+	}
+
+	pub(crate) fn is_recording(&self) -> bool {
+		matches!(self, Input::Recording { .. })
+	}
+
+	pub(crate) fn is_replay(&self) -> bool {
+		matches!(self, Input::Playing { .. })
+	}
+
+	// Once a replay has run out or gone wrong, how it ended; None while it's going, or when not replaying
+	pub(crate) fn replay_ended(&self) -> Option<&Result<(), String>> {
+		match self {
+			Input::Playing { ended, .. } => ended.as_ref(),
+			_ => None,
+		}
+	}
+
+	// The next event, if there is one, without waiting for it
+	pub(crate) fn check_for_event(&mut self, flags: EventFlags) -> Option<Event> {
+		if let Input::Playing { .. } = self {
+			return match self.next(Want::Event) {
+				Some(Recorded::Key(key)) => Some(Event::Key(key.into())),
+				Some(Recorded::Mouse(mouse)) => Some(Event::Mouse(mouse)),
+				_ => None,
+			};
+		}
+		let event = input::check_for_event(flags).map(|(_, event)| event);
+		match event {
+			Some(Event::Key(key)) => self.write(Recorded::Key(key.into())),
+			Some(Event::Mouse(mouse)) => self.write(Recorded::Mouse(mouse)),
+			None => if let Input::Recording { idle, .. } = self {
+				*idle += 1;
+			},
+		}
+		event
+	}
+
+	// The next key press, waiting for one. A replay that has ended hands back Escape, which backs
+	// out of whatever was asking
+	pub(crate) fn wait_for_keypress(&mut self, root: &mut Root) -> Key {
+		if let Input::Playing { .. } = self {
+			return match self.next(Want::Key) {
+				Some(Recorded::Key(key)) => key.into(),
+				_ => {
+					let mut key = Key::default();
+					key.code = KeyCode::Escape;
+					key
+				}
+			};
+		}
+		let key = root.wait_for_keypress(true);
+		self.write(Recorded::Key(key.into()));
+		key
+	}
+
+	// A seed for a new run: a random one, unless it's played back from the recording
+	pub(crate) fn new_seed(&mut self) -> u64 {
+		if let Input::Playing { .. } = self {
+			if let Some(Recorded::Seed(seed)) = self.next(Want::Seed) {
+				return seed;
+			}
+		}
+		let seed = rand::random();
+		self.write(Recorded::Seed(seed));
+		seed
+	}
+
+	// Called at the end of every turn; a replay checks that it ended the way the recorded one did
+	pub(crate) fn end_turn(&mut self, objects: &[Object], game: &Game) {
+		let checkpoint = Checkpoint::new(objects, game);
+		if let Input::Playing { .. } = self {
+			if let Some(Recorded::Turn(recorded)) = self.next(Want::Turn) {
+				if recorded != checkpoint {
+					self.diverge(format!("turn {} ended with {:?} instead of the recorded {:?}",
+						checkpoint.turn, checkpoint, recorded));
+				} else if let Input::Playing { ref mut turn, .. } = self {
+					*turn = checkpoint.turn;
+				}
+			}
+			return;
+		}
+		self.write(Recorded::Turn(checkpoint));
+		// a recording is most useful when the game crashes, so don't keep turns waiting
+		if let Input::Recording { ref mut file, .. } = self {
+			let _ = file.flush();
+		}
+	}
+
+	// The next recorded entry, for a replay that's still going. Anything but what the game wants
+	// means the replay has diverged
+	fn next(&mut self, want: Want) -> Option<Recorded> {
+		let (entries, ended) = match self {
+			Input::Playing { entries, ended, .. } => (entries, ended),
+			_ => return None,
+		};
+		if ended.is_some() {
+			return None;
+		}
+		let entry = match entries.pop_front() {
+			Some(Recorded::Idle(polls)) if polls > 1 => {
+				entries.push_front(Recorded::Idle(polls - 1));
+				Recorded::Idle(1)
+			}
+			Some(entry) => entry,
+			None => {
+				*ended = Some(Ok(()));
+				return None;
+			}
+		};
+		let expected = match entry {
+			Recorded::Key(_) => want == Want::Event || want == Want::Key,
+			Recorded::Mouse(_) | Recorded::Idle(_) => want == Want::Event,
+			Recorded::Seed(_) => want == Want::Seed,
+			Recorded::Turn(_) => want == Want::Turn,
+			Recorded::Start { .. } => false,
+		};
+		if !expected {
+			self.diverge(format!("the game {} where the recording has {:?}", want.doing(), entry));
+			return None;
+		}
+		Some(entry)
+	}
+
+	fn diverge(&mut self, what: String) {
+		if let Input::Playing { turn, ended, .. } = self {
+			*ended = Some(Err(format!("The replay diverged after turn {}: {}.", turn, what)));
+		}
+	}
+
+	fn write(&mut self, entry: Recorded) {
+		let (path, file, idle) = match self {
+			Input::Recording { path, file, idle } => (path, file, idle),
+			_ => return,
+		};
+		let mut text = String::new();
+		if *idle > 0 {
+			text += &serde_json::to_string(&Recorded::Idle(*idle)).unwrap();
+			text += "\n";
+			*idle = 0;
+		}
+		text += &serde_json::to_string(&entry).unwrap();
+		text += "\n";
+		if let Err(error) = file.write_all(text.as_bytes()) {
+			eprintln!("Could not record to {}, stopping the recording: {}", path, error);
+			*self = Input::Live;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::tests::open_arena;
+
+	fn playing(entries: Vec<Recorded>) -> Input {
+		Input::Playing { entries: entries.into(), turn: 0, ended: None }
+	}
+
+	fn key(printable: char) -> Key {
+		let mut key = Key::default();
+		key.code = KeyCode::Char;
+		key.printable = printable;
+		key.pressed = true;
+		key
+	}
+
+	#[test]
+	fn recorded_inputs_survive_the_file() {
+		let entry = Recorded::Key(key('g').into());
+		let text = serde_json::to_string(&entry).unwrap();
+		assert_eq!(serde_json::from_str::<Recorded>(&text).unwrap(), entry);
+		assert_eq!(Key::from(RecordedKey::from(key('g'))), key('g'));
+	}
+
+	#[test]
+	fn replays_hand_back_what_was_recorded() {
+		let (objects, game) = open_arena();
+		let checkpoint = Checkpoint::new(&objects, &game);
+		let mut replay = playing(vec![Recorded::Idle(2), Recorded::Key(key('g').into()), Recorded::Turn(checkpoint)]);
+		assert!(replay.check_for_event(input::KEY_PRESS).is_none());
+		assert!(replay.check_for_event(input::KEY_PRESS).is_none());
+		assert!(matches!(replay.check_for_event(input::KEY_PRESS), Some(Event::Key(k)) if k == key('g')));
+		replay.end_turn(&objects, &game);
+		assert_eq!(replay.replay_ended(), None);
+		assert!(replay.check_for_event(input::KEY_PRESS).is_none());
+		assert_eq!(replay.replay_ended(), Some(&Ok(())));
+	}
+
+	#[test]
+	fn replays_notice_when_they_diverge() {
+		let (mut objects, game) = open_arena();
+		let checkpoint = Checkpoint::new(&objects, &game);
+		objects[PLAYER].set_pos(11, 10);
+		let mut replay = playing(vec![Recorded::Turn(checkpoint)]);
+		replay.end_turn(&objects, &game);
+		assert!(matches!(replay.replay_ended(), Some(Err(_))));
+
+		// the recorded turn ended, but this time the game wants another key
+		let mut replay = playing(vec![Recorded::Turn(checkpoint)]);
+		assert!(replay.check_for_event(input::KEY_PRESS).is_none());
+		assert!(matches!(replay.replay_ended(), Some(Err(_))));
+	}
+}